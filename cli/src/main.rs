@@ -1,69 +1,619 @@
-use clap::Parser;
-use kindle_clippings::output::render_output;
-use kindle_clippings::{parse_clippings, read_file_string};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use kindle_clippings::anki::{render_anki, CardType};
+use kindle_clippings::atomic::{render_atomic, DEFAULT_NOTE_NAME};
+use kindle_clippings::epub::render_epub;
+use kindle_clippings::filename::DEFAULT_FILE_NAME;
+use kindle_clippings::html::render_html;
+use kindle_clippings::json::{render_json, save_library};
+use kindle_clippings::manifest::{render_digest, Manifest};
+use kindle_clippings::model::Book;
+use kindle_clippings::output::{self, render_csv, render_to, RenderError};
+use kindle_clippings::preview::Change;
+use kindle_clippings::renderer::Renderers;
+use kindle_clippings::rules::Rules;
+use kindle_clippings::source::{
+    source_by_name, source_for_path, source_names, ClippingSource, Import,
+};
+use kindle_clippings::sync::SyncOutcome;
+use kindle_clippings::validate::{sample_book, validate_template};
+use std::collections::HashMap;
 use std::env;
 use std::fs::create_dir;
 use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
-#[command(version, about, long_about)]
+#[command(
+    version,
+    about,
+    long_about,
+    subcommand_negates_reqs = true,
+    args_conflicts_with_subcommands = true
+)]
 struct Cli {
-    #[arg(value_name = "CLIPPINGS_FILE")]
-    file: PathBuf,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    #[arg(short, long, value_name = "TEMPLATE_FILE")]
+    #[arg(value_name = "CLIPPINGS_FILE", required = true)]
+    file: Option<PathBuf>,
+
+    /// Where the clippings file came from, guessed from its extension if not given
+    #[arg(short, long, value_name = "SOURCE")]
+    source: Option<String>,
+
+    /// A template file, or a directory of templates with one named after the format, such as
+    /// markdown.md, org.org or logseq.md
+    #[arg(short, long, value_name = "TEMPLATE")]
     template: Option<PathBuf>,
 
     #[arg(short, long, value_name = "OUTPUT_DIR")]
     output: Option<PathBuf>,
+
+    /// A TOML file of rules that pick a template and output subfolder for books by their title or
+    /// author, for the markdown, org, logseq and atomic formats
+    #[arg(long, value_name = "RULES_FILE")]
+    rules: Option<PathBuf>,
+
+    /// What to write to the output directory
+    #[arg(short, long, value_name = "FORMAT", value_enum, default_value_t = Format::Markdown)]
+    format: Format,
+
+    /// How highlights are turned into cards for the anki format
+    #[arg(long, value_name = "CARD_TYPE", value_enum, default_value_t = CardArg::Cloze)]
+    card_type: CardArg,
+
+    /// Name of the Anki deck, each book gets a sub-deck
+    #[arg(long, value_name = "DECK", default_value = "Highlights")]
+    deck: String,
+
+    /// How each note is named, a template using title, author or id that can include / to write
+    /// to subdirectories
+    #[arg(long, value_name = "NAME_TEMPLATE", default_value = DEFAULT_FILE_NAME)]
+    file_name: String,
+
+    /// How each note is named in the atomic format, a template using timestamp, slug, id, page,
+    /// location, title or author
+    #[arg(long, value_name = "NAME_TEMPLATE", default_value = DEFAULT_NOTE_NAME)]
+    note_name: String,
+
+    /// Keep existing notes, only adding highlights they don't have yet, for the markdown, org and
+    /// logseq formats
+    #[arg(long)]
+    sync: bool,
+
+    /// Keep the previous version of each note that's rewritten as NAME.bak, for the markdown, org
+    /// and logseq formats
+    #[arg(long)]
+    backup: bool,
+
+    /// Only export highlights that are new or changed since the last run with this flag, as
+    /// recorded in the output directory's .ktr-manifest.json, for the markdown, org, logseq and
+    /// atomic formats
+    #[arg(long)]
+    only_new: bool,
+
+    /// Write a "New since last sync" note of the highlights that are new or changed since the last
+    /// run with this flag or --only-new
+    #[arg(long)]
+    digest: bool,
+
+    /// Show what would be written, with a diff of each note that would change, without writing
+    /// anything, for the markdown, org and logseq formats
+    #[arg(long)]
+    dry_run: bool,
+
+    /// Print the note for the book whose title contains TITLE to stdout instead of writing to the
+    /// output directory, for the markdown, org and logseq formats
+    #[arg(long, value_name = "TITLE")]
+    print: Option<String>,
+
+    /// Collect every book into a single commonplace.epub for the epub format
+    #[arg(long)]
+    commonplace: bool,
+
+    /// Save a snapshot of the parsed library, which can be used as the clippings file next time
+    #[arg(long, value_name = "LIBRARY_FILE")]
+    save_library: Option<PathBuf>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Check a template by rendering it against a sample book, or a book from a clippings file,
+    /// reporting syntax errors and undefined variables along with the rendered note
+    CheckTemplate(CheckArgs),
+}
+
+#[derive(Args)]
+struct CheckArgs {
+    /// The template file or directory to check
+    #[arg(value_name = "TEMPLATE")]
+    template: PathBuf,
+
+    /// The format to check the template for, markdown, org or logseq
+    #[arg(short, long, value_name = "FORMAT", value_enum, default_value_t = Format::Markdown)]
+    format: Format,
+
+    /// Render a book from this clippings file instead of the sample book
+    #[arg(long, value_name = "CLIPPINGS_FILE", requires = "book")]
+    clippings: Option<PathBuf>,
+
+    /// The book from the clippings file whose title contains TITLE
+    #[arg(long, value_name = "TITLE", requires = "clippings")]
+    book: Option<String>,
+
+    /// Where the clippings file came from, guessed from its extension if not given
+    #[arg(short, long, value_name = "SOURCE")]
+    source: Option<String>,
+}
+
+impl Cli {
+    fn file(&self) -> &Path {
+        self.file
+            .as_deref()
+            .expect("the clippings file is required without a subcommand")
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum Format {
+    /// One file per book, rendered with the template
+    Markdown,
+    /// One org-mode file per book, rendered with the template
+    Org,
+    /// One Logseq page per book, rendered with the template
+    Logseq,
+    /// One note per highlight, rendered with the template, and an index note per book
+    Atomic,
+    /// The whole library as a single library.json file
+    Json,
+    /// Every highlight as a row of a single highlights.csv file
+    Csv,
+    /// An Anki package of cards made from the highlights, highlights.apkg
+    Anki,
+    /// A static website for browsing the library, starting from index.html
+    Html,
+    /// An EPUB of each book's highlights, to read on an e-reader
+    Epub,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum CardArg {
+    /// The quote with its longest word hidden
+    Cloze,
+    /// The note on the front and the quote on the back
+    NoteQuote,
+}
+
+impl Format {
+    /// the library's format for the formats written as a note per book
+    fn per_book(self) -> Option<output::Format> {
+        match self {
+            Format::Markdown => Some(output::Format::Markdown),
+            Format::Org => Some(output::Format::Org),
+            Format::Logseq => Some(output::Format::Logseq),
+            _ => None,
+        }
+    }
+}
+
+impl From<CardArg> for CardType {
+    fn from(value: CardArg) -> Self {
+        match value {
+            CardArg::Cloze => CardType::Cloze,
+            CardArg::NoteQuote => CardType::NoteQuote,
+        }
+    }
 }
 
 fn main() {
     let cli = Cli::parse();
 
+    if let Some(Command::CheckTemplate(args)) = &cli.command {
+        check_template(args);
+        return;
+    }
+
+    let source = clippings_source(&cli.source, cli.file());
+
+    if let Some(flag) = per_book_flag(&cli) {
+        eprintln!(
+            "{} only works with the markdown, org and logseq formats",
+            flag
+        );
+        ::std::process::exit(1);
+    }
+
+    // these write the whole library to one file, which would be left with only what's new
+    if cli.only_new
+        && matches!(
+            cli.format,
+            Format::Json | Format::Csv | Format::Anki | Format::Html | Format::Epub
+        )
+    {
+        eprintln!("--only-new only works with the markdown, org, logseq and atomic formats");
+        ::std::process::exit(1);
+    }
+
+    let rules = match &cli.rules {
+        Some(r) => Rules::load(r).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            ::std::process::exit(1);
+        }),
+        None => Rules::default(),
+    };
+
+    if let Some(title) = &cli.print {
+        print_book(&cli, source.as_ref(), &rules, title);
+        return;
+    }
+
     // create/validate provided output directory
-    if let Some(o) = cli.output {
+    if let Some(o) = &cli.output {
         // create directory if it doesn't exist
-        if !o.exists() {
-            if let Err(e) = create_dir(&o) {
+        if !o.exists() && !cli.dry_run {
+            if let Err(e) = create_dir(o) {
                 eprintln!("Unable to create output directory: {}", e);
                 ::std::process::exit(1);
             }
 
             // check provided dir is actually a directory
-        } else if !o.is_dir() {
+        } else if o.exists() && !o.is_dir() {
             eprintln!("{} is not a directory!", o.display());
             ::std::process::exit(1);
         }
-        run(cli.file, cli.template, &o);
+        run(&cli, source.as_ref(), &rules, o);
 
         // default to "output" directory
     } else {
         let mut pwd = env::current_dir().unwrap();
         pwd.push("output");
 
+        if cli.dry_run {
+            run(&cli, source.as_ref(), &rules, &pwd);
+            return;
+        }
+
         if let Err(e) = create_dir(&pwd) {
             eprintln!("Unable to create output directory: {}", e);
             ::std::process::exit(1);
         }
 
-        run(cli.file, cli.template, &pwd);
+        run(&cli, source.as_ref(), &rules, &pwd);
     }
 }
 
-pub fn run(clippings: PathBuf, template: Option<PathBuf>, output_dir: &Path) {
-    if let Ok(s) = read_file_string(clippings) {
-        let books = parse_clippings(s);
+fn run(cli: &Cli, source: &dyn ClippingSource, rules: &Rules, output_dir: &Path) {
+    match source.import(cli.file()) {
+        Ok(import) => {
+            for d in import.diagnostics.iter() {
+                eprintln!("Skipped {}", d);
+            }
+
+            if let Some(l) = cli.save_library.as_ref().filter(|_| !cli.dry_run) {
+                if let Err(e) = save_library(&import.library, l) {
+                    eprintln!("Unable to save library: {}", e);
+                    ::std::process::exit(1);
+                }
+            }
+
+            let mut manifest = None;
+            let mut changes = None;
+            if cli.only_new || cli.digest {
+                let m = Manifest::load(output_dir).unwrap_or_else(|e| {
+                    eprintln!("Unable to read the sync manifest: {}", e);
+                    ::std::process::exit(1);
+                });
+                let c = m.changes(&import.library);
+
+                for b in c.books.iter() {
+                    println!("{}", b);
+                }
+                println!(
+                    "{} new and {} changed highlight(s) since {}",
+                    c.new_count(),
+                    c.changed_count(),
+                    m.synced_at().map_or("the first sync".to_string(), |d| {
+                        d.format("%Y-%m-%d %H:%M:%S").to_string()
+                    })
+                );
+
+                manifest = Some(m);
+                changes = Some(c);
+            }
+
+            let library = match &changes {
+                Some(c) if cli.only_new => &c.library,
+                _ => &import.library,
+            };
+
+            // notes written per book are rewritten in full, so books with anything new need
+            // every highlight, not only the new ones
+            let books: Vec<&Book> = match &changes {
+                Some(c) if cli.only_new => import
+                    .library
+                    .values()
+                    .filter(|b| c.library.contains_key(b.title()))
+                    .collect(),
+                _ => import.library.values().collect(),
+            };
 
-        for (_, book) in books.iter() {
-            if let Err(e) = render_output(book, &template, output_dir) {
+            let render_books = |format: output::Format| {
+                let mut renderers = Renderers::new(rules, &cli.template, format, &cli.file_name)?;
+                renderers.keep_backups(cli.backup);
+
+                if cli.dry_run {
+                    return match cli.sync {
+                        true => preview_books(library.values(), &renderers, true, output_dir),
+                        false => {
+                            preview_books(books.iter().copied(), &renderers, false, output_dir)
+                        }
+                    };
+                }
+
+                if cli.sync {
+                    return sync_books(library, &renderers, output_dir);
+                }
+
+                write_books(&books, &renderers, output_dir)
+            };
+
+            let result = match cli.format {
+                Format::Markdown => render_books(output::Format::Markdown),
+                Format::Org => render_books(output::Format::Org),
+                Format::Logseq => render_books(output::Format::Logseq),
+                Format::Atomic => books.iter().try_for_each(|book| {
+                    let choice = rules.choose(book, &cli.template, output_dir);
+                    render_atomic(book, choice.template, &cli.note_name, &choice.output_dir)
+                }),
+                Format::Json => render_json(library, output_dir),
+                Format::Csv => render_csv(library, output_dir),
+                Format::Anki => render_anki(library, cli.card_type.into(), &cli.deck, output_dir),
+                Format::Html => render_html(library, output_dir),
+                Format::Epub => render_epub(library, output_dir, cli.commonplace),
+            };
+
+            if let Err(e) = result {
                 eprintln!("{}", e);
                 ::std::process::exit(1);
             }
+
+            if cli.dry_run {
+                return;
+            }
+
+            if let (Some(mut m), Some(c)) = (manifest, changes) {
+                if cli.digest && !c.is_empty() {
+                    match render_digest(&c, output_dir) {
+                        Ok(p) => println!("Wrote {}", p.display()),
+                        Err(e) => eprintln!("Unable to write the digest: {}", e),
+                    }
+                }
+
+                m.record(&import.library);
+                if let Err(e) = m.save(output_dir) {
+                    eprintln!("Unable to save the sync manifest: {}", e);
+                    ::std::process::exit(1);
+                }
+            }
         }
-    } else {
-        panic!("error reading file");
+        Err(e) => {
+            eprintln!("Unable to read {}: {}", cli.file().display(), e);
+            ::std::process::exit(1);
+        }
+    }
+}
+
+/// the first flag given that only works with formats written a note per book, when the format
+/// isn't one of them
+fn per_book_flag(cli: &Cli) -> Option<&'static str> {
+    if cli.format.per_book().is_some() {
+        return None;
+    }
+
+    [
+        ("--dry-run", cli.dry_run),
+        ("--sync", cli.sync),
+        ("--backup", cli.backup),
+    ]
+    .into_iter()
+    .find_map(|(flag, given)| given.then_some(flag))
+}
+
+/// the source named, or the one that reads files like `file`
+fn clippings_source(name: &Option<String>, file: &Path) -> Box<dyn ClippingSource> {
+    let source = match name {
+        Some(s) => source_by_name(s),
+        None => source_for_path(file),
+    };
+
+    source.unwrap_or_else(|| {
+        eprintln!(
+            "Unable to tell where {} came from, use --source with one of: {}",
+            file.display(),
+            source_names().join(", ")
+        );
+        ::std::process::exit(1);
+    })
+}
+
+fn import_library(source: &dyn ClippingSource, file: &Path) -> Import {
+    source.import(file).unwrap_or_else(|e| {
+        eprintln!("Unable to read {}: {}", file.display(), e);
+        ::std::process::exit(1);
+    })
+}
+
+/// the book whose title is `title`, or else the one book whose title contains it, ignoring case
+fn find_book<'a>(library: &'a HashMap<String, Book>, title: &str) -> &'a Book {
+    let needle = title.to_lowercase();
+    let mut matches: Vec<&Book> = library
+        .values()
+        .filter(|b| b.title().to_lowercase().contains(&needle))
+        .collect();
+    matches.sort_by(|a, b| a.title().cmp(b.title()));
+
+    // a title that's part of another, like "Dune" and "Dune Messiah", can still be picked
+    if let Some(book) = matches.iter().find(|b| b.title().to_lowercase() == needle) {
+        return book;
+    }
+
+    match matches.as_slice() {
+        [book] => book,
+        [] => {
+            eprintln!("No book's title contains \"{}\"", title);
+            ::std::process::exit(1);
+        }
+        _ => {
+            eprintln!("More than one book's title contains \"{}\":", title);
+            for b in matches.iter() {
+                eprintln!("  {}", b.title());
+            }
+            ::std::process::exit(1);
+        }
+    }
+}
+
+/// write each book's note, rendering the books for each template in parallel
+fn write_books(
+    books: &[&Book],
+    renderers: &Renderers,
+    output_dir: &Path,
+) -> Result<(), RenderError> {
+    for (renderer, dir, books) in renderers.group(books.iter().copied(), output_dir) {
+        for (_, result) in renderer.write_all(&books, &dir) {
+            result?;
+        }
+    }
+    Ok(())
+}
+
+/// sync each book's note, printing what happened to it and a total at the end
+fn sync_books(
+    library: &HashMap<String, Book>,
+    renderers: &Renderers,
+    output_dir: &Path,
+) -> Result<(), RenderError> {
+    let mut books: Vec<&Book> = library.values().collect();
+    books.sort_by(|a, b| a.title().cmp(b.title()));
+
+    let (mut created, mut updated, mut added) = (0, 0, 0);
+    for (renderer, dir, books) in renderers.group(books, output_dir) {
+        for book in books {
+            let summary = renderer.sync(book, &dir)?;
+            match summary.outcome {
+                SyncOutcome::Created(_) => created += 1,
+                SyncOutcome::Updated(n) => {
+                    updated += 1;
+                    added += n;
+                }
+                SyncOutcome::Unmanaged => {
+                    eprintln!("{}", summary);
+                    continue;
+                }
+                SyncOutcome::Unchanged => continue,
+            }
+            println!("{}", summary);
+        }
+    }
+
+    println!(
+        "{} note(s) created, {} highlight(s) added to {} existing note(s)",
+        created, added, updated
+    );
+    Ok(())
+}
+
+/// print the note for the one book whose title contains `title`
+fn print_book(cli: &Cli, source: &dyn ClippingSource, rules: &Rules, title: &str) {
+    let Some(format) = cli.format.per_book() else {
+        eprintln!("--print only works with the markdown, org and logseq formats");
+        ::std::process::exit(1);
+    };
+
+    let import = import_library(source, cli.file());
+    let book = find_book(&import.library, title);
+
+    let template = rules.choose(book, &cli.template, Path::new("")).template;
+    if let Err(e) = render_to(book, template, format, std::io::stdout().lock()) {
+        eprintln!("{}", e);
+        ::std::process::exit(1);
+    }
+}
+
+/// render the template against a book and report what's wrong with it, exiting with an error if
+/// anything is
+fn check_template(args: &CheckArgs) {
+    let Some(format) = args.format.per_book() else {
+        eprintln!("check-template only works with the markdown, org and logseq formats");
+        ::std::process::exit(1);
+    };
+
+    let import;
+    let sample;
+    let book = match (&args.clippings, &args.book) {
+        (Some(file), Some(title)) => {
+            let source = clippings_source(&args.source, file);
+            import = import_library(source.as_ref(), file);
+            find_book(&import.library, title)
+        }
+        _ => {
+            sample = sample_book();
+            &sample
+        }
+    };
+
+    let report = validate_template(&args.template, format, book);
+    if let Some(rendered) = &report.rendered {
+        println!("{}", rendered);
+    }
+
+    for e in report.errors.iter() {
+        eprintln!("Error: {}", e);
+    }
+    for u in report.undefined.iter() {
+        eprintln!("Error: {}", u);
     }
+
+    if !report.is_ok() {
+        ::std::process::exit(1);
+    }
+    eprintln!(
+        "{} renders {} without any problems",
+        args.template.display(),
+        book.title()
+    );
+}
+
+/// print what writing each book's note would do, with a diff for the notes that would change
+fn preview_books<'a>(
+    books: impl Iterator<Item = &'a Book>,
+    renderers: &Renderers,
+    sync: bool,
+    output_dir: &Path,
+) -> Result<(), RenderError> {
+    let mut books: Vec<&Book> = books.collect();
+    books.sort_by(|a, b| a.title().cmp(b.title()));
+
+    let (mut created, mut modified, mut unchanged) = (0, 0, 0);
+    for (renderer, dir, books) in renderers.group(books, output_dir) {
+        for book in books {
+            let preview = renderer.preview(book, &dir, sync)?;
+            println!("{}", preview);
+            match preview.change {
+                Change::Create => created += 1,
+                Change::Unchanged => unchanged += 1,
+                Change::Modify(diff) => {
+                    modified += 1;
+                    println!("{}", diff);
+                }
+            }
+        }
+    }
+
+    println!(
+        "Dry run: {} note(s) would be created, {} modified and {} unchanged",
+        created, modified, unchanged
+    );
+    Ok(())
 }
 
 #[test]
@@ -71,3 +621,36 @@ fn verify_cli() {
     use clap::CommandFactory;
     Cli::command().debug_assert();
 }
+
+#[test]
+fn per_book_flags() {
+    let flag = |args: &[&str]| {
+        per_book_flag(&Cli::parse_from(
+            ["ktr", "My Clippings.txt"].iter().chain(args.iter()),
+        ))
+    };
+
+    assert_eq!(None, flag(&["--sync"]));
+    assert_eq!(Some("--sync"), flag(&["--sync", "--format", "atomic"]));
+    assert_eq!(Some("--sync"), flag(&["--sync", "--format", "json"]));
+    assert_eq!(Some("--dry-run"), flag(&["--dry-run", "--format", "csv"]));
+    assert_eq!(Some("--backup"), flag(&["--backup", "--format", "html"]));
+    assert_eq!(None, flag(&["--backup", "--format", "logseq"]));
+    assert_eq!(None, flag(&["--format", "csv"]));
+}
+
+#[test]
+fn find_exact_title() {
+    let library: HashMap<String, Book> = ["Dune", "Dune Messiah"]
+        .map(|t| {
+            (
+                t.to_string(),
+                Book::new(t.to_string(), "Herbert, Frank".to_string()),
+            )
+        })
+        .into_iter()
+        .collect();
+
+    assert_eq!("Dune", find_book(&library, "dune").title());
+    assert_eq!("Dune Messiah", find_book(&library, "messiah").title());
+}
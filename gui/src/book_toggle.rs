@@ -9,13 +9,15 @@ use iced::{
     Size, Theme,
 };
 
+type OnToggle<'a, Message> = Box<dyn Fn((bool, String)) -> Message + 'a>;
+
 pub struct BookToggler<'a, Message, Theme = iced::Theme, Renderer = iced::Renderer>
 where
     Theme: Catalog,
     Renderer: text::Renderer,
 {
     is_toggled: bool,
-    on_toggle: Option<Box<dyn Fn((bool, String)) -> Message + 'a>>,
+    on_toggle: Option<OnToggle<'a, Message>>,
     label: Option<text::Fragment<'a>>,
     width: Length,
     size: f32,
@@ -3,13 +3,20 @@
 use std::collections::HashMap;
 
 use iced::widget::{
-    button, column, container, horizontal_space, row, scrollable, text, text_input, toggler, Space,
+    button, column, container, horizontal_space, pick_list, row, scrollable, text, text_input,
+    toggler, Space,
 };
 use iced::widget::{Button, Column};
-use iced::{Center, Element, Fill, Task, Theme};
+use iced::{Center, Element, Fill, Font, Task, Theme};
+use kindle_clippings::filename::DEFAULT_FILE_NAME;
+use kindle_clippings::json::save_library;
 use kindle_clippings::model::Book;
-use kindle_clippings::output::render_output;
-use kindle_clippings::{parse_clippings, read_file_string};
+use kindle_clippings::output::Format;
+use kindle_clippings::preview::{Change, Preview};
+use kindle_clippings::renderer::Renderers;
+use kindle_clippings::rules::Rules;
+use kindle_clippings::source::{source_for_path, Diagnostic, Import};
+use kindle_clippings::sync::SyncOutcome;
 use rfd::{AsyncFileDialog, FileHandle};
 
 use self::book_toggle::BookToggler;
@@ -28,12 +35,21 @@ pub struct Ktr {
     input: Option<FileHandle>,
     use_template: bool,
     template: Option<FileHandle>,
+    format: Format,
     output_dir: Option<FileHandle>,
+    sync: bool,
+    rules_file: Option<FileHandle>,
+    rules: Rules,
+    rules_error: Option<String>,
     library: HashMap<String, Book>,
+    diagnostics: Vec<Diagnostic>,
     filter_text: String,
     filtered_library: HashMap<String, Book>,
     selected_library: HashMap<String, Book>,
+    library_saved: Option<String>,
+    previews: Option<Vec<Preview>>,
     output_created: bool,
+    sync_summary: Vec<String>,
 }
 
 #[derive(Debug, Clone)]
@@ -42,17 +58,25 @@ enum Message {
     NextPressed,
     OpenClippings,
     InputChanged(Option<FileHandle>),
-    LibraryChanged(HashMap<String, Book>),
+    LibraryChanged(Import),
     OpenTemplate,
+    OpenTemplateDir,
     UseTemplate(bool),
     TemplateChanged(Option<FileHandle>),
+    FormatSelected(Format),
     BookToggled((bool, String)),
     FilterTextChanged(String),
     SelectAllBooks,
     SelectNoBooks,
+    SaveLibrary,
+    LibrarySaved(Option<String>),
     ChooseOutputDir,
     OutputDirChanged(Option<FileHandle>),
-    OutputCreated(bool),
+    SyncToggled(bool),
+    OpenRules,
+    RulesChanged(Option<FileHandle>),
+    PreviewReady(Vec<Preview>),
+    OutputCreated(Vec<String>),
     Exit,
 }
 
@@ -75,12 +99,29 @@ impl Ktr {
             Message::NextPressed => {
                 if let Some(screen) = self.screen.next() {
                     self.screen = screen;
+                    if screen == Screen::Preview {
+                        self.previews = None;
+                        return Task::perform(
+                            preview_reference_files(
+                                self.selected_library.clone(),
+                                self.template.clone(),
+                                self.format,
+                                self.output_dir.clone(),
+                                self.rules.clone(),
+                                self.sync,
+                            ),
+                            Message::PreviewReady,
+                        );
+                    }
                     if screen == Screen::End {
                         return Task::perform(
                             create_reference_files(
                                 self.selected_library.clone(),
                                 self.template.clone(),
+                                self.format,
                                 self.output_dir.clone(),
+                                self.rules.clone(),
+                                self.sync,
                             ),
                             Message::OutputCreated,
                         );
@@ -99,19 +140,27 @@ impl Ktr {
                     );
                 }
             }
-            Message::LibraryChanged(l) => {
-                self.library = l;
+            Message::LibraryChanged(i) => {
+                self.library = i.library;
+                self.diagnostics = i.diagnostics;
+                self.library_saved = None;
                 self.filtered_library = self.library.clone();
             }
             Message::OpenTemplate => {
                 return Task::perform(open_template(), Message::TemplateChanged)
             }
+            Message::OpenTemplateDir => {
+                return Task::perform(open_template_dir(), Message::TemplateChanged)
+            }
             Message::UseTemplate(u) => {
                 self.use_template = u;
             }
             Message::TemplateChanged(t) => {
                 self.template = t;
             }
+            Message::FormatSelected(f) => {
+                self.format = f;
+            }
             Message::BookToggled((t, b)) => {
                 if t {
                     if let std::collections::hash_map::Entry::Vacant(e) =
@@ -146,14 +195,48 @@ impl Ktr {
             Message::SelectNoBooks => {
                 self.selected_library.clear();
             }
+            Message::SaveLibrary => {
+                return Task::perform(
+                    save_library_snapshot(self.library.clone()),
+                    Message::LibrarySaved,
+                )
+            }
+            Message::LibrarySaved(s) => {
+                self.library_saved = s;
+            }
             Message::ChooseOutputDir => {
                 return Task::perform(open_target_dir(), Message::OutputDirChanged)
             }
             Message::OutputDirChanged(o) => {
                 self.output_dir = o;
             }
-            Message::OutputCreated(b) => {
-                self.output_created = b;
+            Message::SyncToggled(s) => {
+                self.sync = s;
+            }
+            Message::OpenRules => return Task::perform(open_rules(), Message::RulesChanged),
+            Message::RulesChanged(r) => {
+                let loaded = match &r {
+                    Some(f) => Rules::load(f.path()),
+                    None => Ok(Rules::default()),
+                };
+                match loaded {
+                    Ok(rules) => {
+                        self.rules = rules;
+                        self.rules_error = None;
+                    }
+                    Err(e) => {
+                        self.rules = Rules::default();
+                        self.rules_error = Some(e.to_string());
+                    }
+                }
+                self.rules_file = r;
+            }
+            Message::PreviewReady(previews) => {
+                self.previews = Some(previews);
+            }
+            Message::OutputCreated(summary) => {
+                self.output_created = true;
+                self.sync_summary = summary;
             }
             Message::Exit => {
                 ::std::process::exit(0);
@@ -162,7 +245,7 @@ impl Ktr {
         Task::none()
     }
 
-    fn view(&self) -> Element<Message> {
+    fn view(&self) -> Element<'_, Message> {
         let pager_controls = row![]
             .push_maybe(self.screen.previous().is_some().then(|| {
                 padded_button("Back")
@@ -183,6 +266,7 @@ impl Ktr {
             Screen::SelectInput => self.select_input(),
             Screen::BookSelection => self.book_selection(),
             Screen::SelectOutput => self.select_output(),
+            Screen::Preview => self.preview(),
             Screen::End => self.end(),
         };
 
@@ -204,7 +288,7 @@ impl Ktr {
         container(scrollable).center_y(Fill).into()
     }
 
-    fn select_input(&self) -> Column<Message> {
+    fn select_input(&self) -> Column<'_, Message> {
         let selected_file = match &self.input {
             Some(f) => f.path().to_str().unwrap(),
             None => "None",
@@ -225,37 +309,63 @@ impl Ktr {
             .label("Use a custom output template?")
             .on_toggle(Message::UseTemplate);
 
-        let template_input = text_input("Template file...", selected_template)
+        let template_input = text_input("Template file or directory...", selected_template)
             .padding(10)
             .size(20);
 
         let template_btn = button("Open").padding(10).on_press(Message::OpenTemplate);
 
+        let template_dir_btn = button("Folder")
+            .padding(10)
+            .style(button::secondary)
+            .on_press(Message::OpenTemplateDir);
+
+        let format_list = pick_list(Format::ALL, Some(self.format), Message::FormatSelected);
+
         if self.use_template {
             Self::container("Input Selection")
-                .push("Open your 'My Clippings.txt' file")
+                .push("Open your 'My Clippings.txt', 'KoboReader.sqlite' or saved library file")
                 .push(row![file_input, clippings_btn].spacing(10).align_y(Center))
                 .push(Space::new(0, 20))
                 .push(row![template_toggle])
                 .push(
-                    row![template_input, template_btn]
+                    row![template_input, template_btn, template_dir_btn]
+                        .spacing(10)
+                        .align_y(Center),
+                )
+                .push(Space::new(0, 20))
+                .push(
+                    row!["Output format", format_list]
                         .spacing(10)
                         .align_y(Center),
                 )
                 .push(Space::new(0, 20))
         } else {
             Self::container("Input Selection")
-                .push("Open your 'My Clippings.txt' file")
+                .push("Open your 'My Clippings.txt', 'KoboReader.sqlite' or saved library file")
                 .push(row![file_input, clippings_btn].spacing(10).align_y(Center))
                 .push(Space::new(0, 20))
                 .push(row![template_toggle])
                 .push(Space::new(0, 20))
+                .push(
+                    row!["Output format", format_list]
+                        .spacing(10)
+                        .align_y(Center),
+                )
+                .push(Space::new(0, 20))
         }
     }
 
-    fn book_selection(&self) -> Column<Message> {
+    fn book_selection(&self) -> Column<'_, Message> {
         let mut out = Self::container("Book Selection");
 
+        if !self.diagnostics.is_empty() {
+            out = out.push(text(format!(
+                "{} problem(s) reading your clippings file",
+                self.diagnostics.len()
+            )));
+        }
+
         if self.library.is_empty() {
             out = out.push("No books found in your clippings file")
         } else {
@@ -273,9 +383,20 @@ impl Ktr {
                 .padding(10)
                 .on_press(Message::SelectAllBooks);
 
+            let save_btn = button("Save Library")
+                .padding(10)
+                .style(button::secondary)
+                .on_press(Message::SaveLibrary);
+
             out = out.push(row![filter_input]);
             out = out
                 .push(row![select_none_btn, horizontal_space(), select_all_btn])
+                .push(
+                    row![save_btn]
+                        .push_maybe(self.library_saved.as_deref().map(text))
+                        .spacing(10)
+                        .align_y(Center),
+                )
                 .push(Space::new(0, 20));
 
             for (title, _book) in self.filtered_library.iter() {
@@ -290,7 +411,7 @@ impl Ktr {
         out
     }
 
-    fn select_output(&self) -> Column<Message> {
+    fn select_output(&self) -> Column<'_, Message> {
         let selected_dir = match &self.output_dir {
             Some(f) => f.path().to_str().unwrap(),
             None => "None",
@@ -304,15 +425,62 @@ impl Ktr {
             .padding(10)
             .on_press(Message::ChooseOutputDir);
 
+        let sync_toggle = toggler(self.sync)
+            .label("Keep edits made to existing notes, only adding new highlights?")
+            .on_toggle(Message::SyncToggled);
+
+        let selected_rules = match &self.rules_file {
+            Some(f) => f.path().to_str().unwrap(),
+            None => "None",
+        };
+
+        let rules_input = text_input("Rules file...", selected_rules)
+            .padding(10)
+            .size(20);
+
+        let rules_btn = button("Open").padding(10).on_press(Message::OpenRules);
+
         Self::container("Selecting output")
             .push("Choose your output directory")
             .push(row![output_dir, output_btn].spacing(10).align_y(Center))
             .push(Space::new(0, 20))
+            .push(row![sync_toggle])
+            .push(Space::new(0, 20))
+            .push("Optionally, choose a rules file to pick templates and folders for each book")
+            .push(row![rules_input, rules_btn].spacing(10).align_y(Center))
+            .push_maybe(self.rules_error.as_deref().map(text))
+            .push(Space::new(0, 20))
+    }
+
+    fn preview(&self) -> Column<'_, Message> {
+        let Some(previews) = &self.previews else {
+            return Self::container("Working...");
+        };
+
+        let mut out = Self::container("Preview")
+            .push("These notes will be written when you continue")
+            .push(Space::new(0, 20));
+
+        for preview in previews.iter() {
+            out = out.push(text(preview.to_string()));
+            if let Change::Modify(diff) = &preview.change {
+                out = out.push(text(diff).font(Font::MONOSPACE).size(12));
+            }
+        }
+
+        out.push(Space::new(0, 20))
     }
 
-    fn end(&self) -> Column<Message> {
+    fn end(&self) -> Column<'_, Message> {
         if self.output_created {
-            Self::container("Done!").push(Space::new(0, 20))
+            let summary = self
+                .sync_summary
+                .iter()
+                .fold(column![].spacing(5), |c, s| c.push(text(s)));
+
+            Self::container("Done!")
+                .push(scrollable(summary))
+                .push(Space::new(0, 20))
         } else {
             Self::container("Working...")
         }
@@ -322,7 +490,8 @@ impl Ktr {
         match self.screen {
             Screen::SelectInput => self.input.is_some(),
             Screen::BookSelection => !self.selected_library.is_empty(),
-            Screen::SelectOutput => self.output_dir.is_some(),
+            Screen::SelectOutput => self.output_dir.is_some() && self.rules_error.is_none(),
+            Screen::Preview => self.previews.is_some(),
             Screen::End => false,
         }
     }
@@ -337,6 +506,7 @@ enum Screen {
     SelectInput,
     BookSelection,
     SelectOutput,
+    Preview,
     End,
 }
 
@@ -345,6 +515,7 @@ impl Screen {
         Self::SelectInput,
         Self::BookSelection,
         Self::SelectOutput,
+        Self::Preview,
         Self::End,
     ];
 
@@ -382,7 +553,7 @@ fn padded_button<Message: Clone>(label: &str) -> Button<'_, Message> {
 
 async fn open_clippings() -> Option<FileHandle> {
     AsyncFileDialog::new()
-        .add_filter("text", &["txt"])
+        .add_filter("clippings", &["txt", "sqlite", "csv", "pdf", "json"])
         .pick_file()
         .await
 }
@@ -394,33 +565,121 @@ async fn open_template() -> Option<FileHandle> {
         .await
 }
 
+async fn open_template_dir() -> Option<FileHandle> {
+    AsyncFileDialog::new().pick_folder().await
+}
+
+async fn open_rules() -> Option<FileHandle> {
+    AsyncFileDialog::new()
+        .add_filter("rules", &["toml"])
+        .pick_file()
+        .await
+}
+
+async fn save_library_snapshot(library: HashMap<String, Book>) -> Option<String> {
+    let file = AsyncFileDialog::new()
+        .add_filter("library", &["json"])
+        .set_file_name("library.json")
+        .save_file()
+        .await?;
+
+    match save_library(&library, file.path()) {
+        Ok(_) => Some("Saved".to_string()),
+        Err(e) => Some(format!("Unable to save: {}", e)),
+    }
+}
+
 async fn open_target_dir() -> Option<FileHandle> {
     AsyncFileDialog::new().pick_folder().await
 }
 
-async fn parse_library(clippings: FileHandle) -> HashMap<String, Book> {
-    let mut books: HashMap<String, Book> = HashMap::new();
-    if let Ok(s) = read_file_string(clippings.path()) {
-        books = parse_clippings(s);
+async fn parse_library(clippings: FileHandle) -> Import {
+    let result = match source_for_path(clippings.path()) {
+        Some(source) => source.import(clippings.path()).map_err(|e| e.to_string()),
+        None => Err("unrecognised file type".to_string()),
+    };
+
+    match result {
+        Ok(import) => import,
+        Err(e) => Import {
+            library: HashMap::new(),
+            diagnostics: vec![Diagnostic::new(None, e)],
+        },
     }
+}
 
-    books
+async fn preview_reference_files(
+    lib: HashMap<String, Book>,
+    template: Option<FileHandle>,
+    format: Format,
+    output_dir: Option<FileHandle>,
+    rules: Rules,
+    sync: bool,
+) -> Vec<Preview> {
+    let template = template.map(|t| t.path().to_path_buf());
+    let output_dir = output_dir.unwrap();
+
+    let renderers = match Renderers::new(&rules, &template, format, DEFAULT_FILE_NAME) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("{}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut previews: Vec<Preview> = Vec::new();
+    for (renderer, dir, books) in renderers.group(lib.values(), output_dir.path()) {
+        for book in books {
+            match renderer.preview(book, &dir, sync) {
+                Ok(p) => previews.push(p),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+    previews.sort_by(|a, b| a.file_path.cmp(&b.file_path));
+    previews
 }
 
 async fn create_reference_files(
     lib: HashMap<String, Book>,
     template: Option<FileHandle>,
+    format: Format,
     output_dir: Option<FileHandle>,
-) -> bool {
+    rules: Rules,
+    sync: bool,
+) -> Vec<String> {
     let template = template.map(|t| t.path().to_path_buf());
+    let output_dir = output_dir.unwrap();
 
-    for (_, book) in lib.iter() {
-        if let Err(e) = render_output(book, &template, output_dir.clone().unwrap().path()) {
+    let renderers = match Renderers::new(&rules, &template, format, DEFAULT_FILE_NAME) {
+        Ok(r) => r,
+        Err(e) => {
             eprintln!("{}", e);
+            return Vec::new();
+        }
+    };
+
+    let mut summary: Vec<String> = Vec::new();
+    for (renderer, dir, books) in renderers.group(lib.values(), output_dir.path()) {
+        if !sync {
+            for (_, result) in renderer.write_all(&books, &dir) {
+                if let Err(e) = result {
+                    eprintln!("{}", e);
+                }
+            }
+            continue;
         }
-    }
 
-    true
+        for book in books {
+            match renderer.sync(book, &dir) {
+                Ok(s) if s.outcome == SyncOutcome::Unchanged => (),
+                Ok(s) => summary.push(s.to_string()),
+                Err(e) => eprintln!("{}", e),
+            }
+        }
+    }
+    summary.sort();
+    summary
 }
 
 impl Default for Ktr {
@@ -429,13 +688,22 @@ impl Default for Ktr {
             screen: Screen::SelectInput,
             input: None,
             template: None,
+            format: Format::default(),
             output_dir: None,
+            sync: false,
+            rules_file: None,
+            rules: Rules::default(),
+            rules_error: None,
             use_template: false,
             library: HashMap::new(),
+            diagnostics: Vec::new(),
             filter_text: "".to_string(),
             filtered_library: HashMap::new(),
             selected_library: HashMap::new(),
+            library_saved: None,
+            previews: None,
             output_created: false,
+            sync_summary: Vec::new(),
         }
     }
 }
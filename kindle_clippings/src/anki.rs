@@ -0,0 +1,528 @@
+//! Anki deck export, so highlights can be reviewed with spaced repetition.
+//!
+//! An `.apkg` is a zip holding an Anki collection (a SQLite database) and a media manifest. Note
+//! GUIDs are derived from the book and [HighlightLocation], so importing a re-exported deck
+//! updates the existing cards rather than duplicating them.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::Utc;
+use rusqlite::{params, Connection};
+use sha1::{Digest, Sha1};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+use crate::model::{Book, Highlight, HighlightLocation};
+use crate::output::RenderError;
+
+/// name of the file written by [render_anki]
+pub const ANKI_FILE_NAME: &str = "highlights.apkg";
+
+/// How each highlight is turned into a card.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardType {
+    /// the quote with its longest word hidden, the book is shown as a hint
+    Cloze,
+    /// the note on the front and the quote on the back, highlights without a note are skipped
+    NoteQuote,
+}
+
+impl CardType {
+    fn model(&self) -> Model {
+        match self {
+            CardType::Cloze => Model {
+                name: "ktr Cloze",
+                cloze: true,
+                fields: &["Text", "Source", "Note"],
+                front: "{{cloze:Text}}<br><br><small>{{Source}}</small>",
+                back:
+                    "{{cloze:Text}}<br><br><small>{{Source}}</small>{{#Note}}<hr>{{Note}}{{/Note}}",
+            },
+            CardType::NoteQuote => Model {
+                name: "ktr Note/Quote",
+                cloze: false,
+                fields: &["Note", "Quote", "Source"],
+                front: "{{Note}}",
+                back: "{{FrontSide}}<hr id=answer>{{Quote}}<br><br><small>{{Source}}</small>",
+            },
+        }
+    }
+
+    /// the fields of the note for a highlight, or [None] if the highlight can't make this card
+    fn fields(&self, book: &Book, highlight: &Highlight) -> Option<Vec<String>> {
+        let source = escape(&format!("{} — {}", book.title(), book.author()));
+        let note = highlight
+            .note()
+            .as_ref()
+            .map(|n| escape(n.content()))
+            .unwrap_or_default();
+
+        match self {
+            CardType::Cloze => Some(vec![cloze(highlight.quote().trim()), source, note]),
+            CardType::NoteQuote if !note.is_empty() => {
+                Some(vec![note, escape(highlight.quote().trim()), source])
+            }
+            CardType::NoteQuote => None,
+        }
+    }
+}
+
+struct Model {
+    name: &'static str,
+    cloze: bool,
+    fields: &'static [&'static str],
+    front: &'static str,
+    back: &'static str,
+}
+
+/// Writes an Anki package of the library's highlights to a `highlights.apkg` file in the output
+/// [Path], with a deck per book under `deck`.
+pub fn render_anki(
+    library: &HashMap<String, Book>,
+    card_type: CardType,
+    deck: &str,
+    output_dir: &Path,
+) -> Result<(), RenderError> {
+    let mut file_path = output_dir.to_path_buf();
+    file_path.push(ANKI_FILE_NAME);
+
+    let file: File = match File::create(file_path.as_path()) {
+        Ok(f) => f,
+        Err(e) => return Err(RenderError::CreateOutputFileFailed(e.to_string())),
+    };
+
+    export_anki(library, card_type, deck, file)
+}
+
+/// Writes an Anki package of the library's highlights to `writer`, with a deck per book under
+/// `deck`.
+pub fn export_anki<W>(
+    library: &HashMap<String, Book>,
+    card_type: CardType,
+    deck: &str,
+    writer: W,
+) -> Result<(), RenderError>
+where
+    W: Write + Seek,
+{
+    let collection = TempFile::new("anki2");
+    build_collection(library, card_type, deck, &collection.0)
+        .map_err(|e| RenderError::WriteFailed(e.to_string()))?;
+
+    let mut db: Vec<u8> = Vec::new();
+    File::open(&collection.0)
+        .and_then(|mut f| f.read_to_end(&mut db))
+        .map_err(|e| RenderError::WriteFailed(e.to_string()))?;
+
+    let to_error = |e: zip::result::ZipError| RenderError::WriteFailed(e.to_string());
+    let mut zip = ZipWriter::new(writer);
+    let options = SimpleFileOptions::default();
+
+    zip.start_file("collection.anki2", options)
+        .map_err(to_error)?;
+    zip.write_all(&db)
+        .map_err(|e| RenderError::WriteFailed(e.to_string()))?;
+    zip.start_file("media", options).map_err(to_error)?;
+    zip.write_all(b"{}")
+        .map_err(|e| RenderError::WriteFailed(e.to_string()))?;
+    zip.finish().map_err(to_error)?;
+
+    Ok(())
+}
+
+/// a file in the temp directory that's removed when dropped
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new(extension: &str) -> Self {
+        let nanos = Utc::now().timestamp_nanos_opt().unwrap_or_default();
+        TempFile(std::env::temp_dir().join(format!(
+            "ktr-{}-{}.{}",
+            std::process::id(),
+            nanos,
+            extension
+        )))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn build_collection(
+    library: &HashMap<String, Book>,
+    card_type: CardType,
+    deck: &str,
+    path: &Path,
+) -> rusqlite::Result<()> {
+    let conn = Connection::open(path)?;
+    conn.execute_batch(SCHEMA)?;
+
+    let now = Utc::now().timestamp();
+    let model = card_type.model();
+    let model_id = stable_id(&[model.name]);
+
+    let mut books: Vec<&Book> = library.values().collect();
+    books.sort_by(|a, b| a.title().cmp(b.title()));
+
+    let mut decks: Vec<(i64, String)> = vec![(stable_id(&[deck]), deck.to_string())];
+    let mut due: i64 = 0;
+
+    for book in books {
+        let deck_name = format!("{}::{}", deck, book.title().replace("::", ":"));
+        let deck_id = stable_id(&[&deck_name]);
+        decks.push((deck_id, deck_name));
+
+        for (location, highlight) in book.highlights() {
+            let Some(fields) = card_type.fields(book, highlight) else {
+                continue;
+            };
+
+            let guid = note_guid(book, location, model.name);
+            let note_id = stable_id(&[&guid]);
+            let sort_field = strip_html(&fields[0]);
+
+            let mut tags: Vec<String> = vec!["ktr".to_string()];
+            tags.extend(highlight.tags().iter().map(|t| t.replace(' ', "_")));
+
+            conn.execute(
+                "INSERT INTO notes VALUES (?1, ?2, ?3, ?4, -1, ?5, ?6, ?7, ?8, 0, '')",
+                params![
+                    note_id,
+                    guid,
+                    model_id,
+                    now,
+                    format!(" {} ", tags.join(" ")),
+                    fields.join("\x1f"),
+                    sort_field,
+                    checksum(&sort_field),
+                ],
+            )?;
+
+            // cloze models only have the one template, and only one deletion is made
+            due += 1;
+            conn.execute(
+                "INSERT INTO cards VALUES (?1, ?2, ?3, 0, ?4, -1, 0, 0, ?5, 0, 0, 0, 0, 0, 0, 0, 0, '')",
+                params![stable_id(&[&guid, "card"]), note_id, deck_id, now, due],
+            )?;
+        }
+    }
+
+    conn.execute(
+        "INSERT INTO col VALUES (1, ?1, ?2, ?3, 11, 0, 0, 0, ?4, ?5, ?6, ?7, '{}')",
+        params![
+            now - now % 86400,
+            now * 1000,
+            now * 1000,
+            COLLECTION_CONFIG,
+            models_json(&model, model_id, decks[0].0, now),
+            decks_json(&decks, now),
+            DECK_CONFIG,
+        ],
+    )?;
+
+    Ok(())
+}
+
+/// the same book, location and card type always give the same GUID
+fn note_guid(book: &Book, location: &HighlightLocation, model: &str) -> String {
+    let digest = Sha1::digest(
+        format!(
+            "{}\x1f{}\x1f{}\x1f{}\x1f{}",
+            book.title(),
+            book.author(),
+            location.start(),
+            location.end(),
+            model
+        )
+        .as_bytes(),
+    );
+
+    digest[..10].iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Anki ids are millisecond timestamps, so keep derived ids positive and within 53 bits
+fn stable_id(parts: &[&str]) -> i64 {
+    let digest = Sha1::digest(parts.join("\x1f").as_bytes());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    (u64::from_be_bytes(bytes) >> 11) as i64
+}
+
+/// Anki's duplicate check uses the first 8 hex digits of the SHA1 of the sort field
+fn checksum(field: &str) -> i64 {
+    let digest = Sha1::digest(field.as_bytes());
+    u32::from_be_bytes([digest[0], digest[1], digest[2], digest[3]]) as i64
+}
+
+/// hide the longest word of the quote, or all of it if there are no long words
+fn cloze(quote: &str) -> String {
+    let longest = quote
+        .split(|c: char| !c.is_alphanumeric() && c != '\'' && c != '’' && c != '-')
+        .filter(|w| w.chars().count() >= 4)
+        .fold("", |longest, w| {
+            if w.chars().count() > longest.chars().count() {
+                w
+            } else {
+                longest
+            }
+        });
+
+    if longest.is_empty() {
+        return format!("{{{{c1::{}}}}}", escape(quote));
+    }
+
+    let (before, after) = quote.split_at(quote.find(longest).unwrap_or(0));
+    format!(
+        "{}{{{{c1::{}}}}}{}",
+        escape(before),
+        escape(longest),
+        escape(&after[longest.len()..])
+    )
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('\n', "<br>")
+}
+
+fn strip_html(s: &str) -> String {
+    let mut out = String::new();
+    let mut in_tag = false;
+
+    for c in s.chars() {
+        match c {
+            '<' => in_tag = true,
+            '>' => in_tag = false,
+            _ if !in_tag => out.push(c),
+            _ => {}
+        }
+    }
+
+    out.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+fn models_json(model: &Model, model_id: i64, deck_id: i64, now: i64) -> String {
+    let fields: Vec<serde_json::Value> = model
+        .fields
+        .iter()
+        .enumerate()
+        .map(|(i, name)| {
+            serde_json::json!({
+                "name": name, "ord": i, "sticky": false, "rtl": false,
+                "font": "Arial", "size": 20, "media": []
+            })
+        })
+        .collect();
+
+    let json = serde_json::json!({
+        model_id.to_string(): {
+            "id": model_id,
+            "name": model.name,
+            "type": if model.cloze { 1 } else { 0 },
+            "mod": now,
+            "usn": -1,
+            "sortf": 0,
+            "did": deck_id,
+            "tmpls": [{
+                "name": if model.cloze { "Cloze" } else { "Card 1" },
+                "ord": 0,
+                "qfmt": model.front,
+                "afmt": model.back,
+                "did": null,
+                "bqfmt": "",
+                "bafmt": ""
+            }],
+            "flds": fields,
+            "css": ".card { font-family: arial; font-size: 20px; text-align: center; color: black; background-color: white; } .cloze { font-weight: bold; color: blue; }",
+            "latexPre": "\\documentclass[12pt]{article}\n\\special{papersize=3in,5in}\n\\usepackage{amssymb,amsmath}\n\\pagestyle{empty}\n\\setlength{\\parindent}{0in}\n\\begin{document}\n",
+            "latexPost": "\\end{document}",
+            "tags": [],
+            "vers": [],
+            "req": [[0, "any", [0]]]
+        }
+    });
+
+    json.to_string()
+}
+
+fn decks_json(decks: &[(i64, String)], now: i64) -> String {
+    let mut json = serde_json::Map::new();
+
+    let default = [(1, "Default".to_string())];
+    for (id, name) in default.iter().chain(decks.iter()) {
+        json.insert(
+            id.to_string(),
+            serde_json::json!({
+                "id": id,
+                "name": name,
+                "desc": "",
+                "mod": now,
+                "usn": -1,
+                "collapsed": false,
+                "newToday": [0, 0],
+                "revToday": [0, 0],
+                "lrnToday": [0, 0],
+                "timeToday": [0, 0],
+                "conf": 1,
+                "dyn": 0,
+                "extendNew": 10,
+                "extendRev": 50
+            }),
+        );
+    }
+
+    serde_json::Value::Object(json).to_string()
+}
+
+const SCHEMA: &str = "
+CREATE TABLE col (
+    id integer primary key, crt integer not null, mod integer not null, scm integer not null,
+    ver integer not null, dty integer not null, usn integer not null, ls integer not null,
+    conf text not null, models text not null, decks text not null, dconf text not null,
+    tags text not null
+);
+CREATE TABLE notes (
+    id integer primary key, guid text not null, mid integer not null, mod integer not null,
+    usn integer not null, tags text not null, flds text not null, sfld integer not null,
+    csum integer not null, flags integer not null, data text not null
+);
+CREATE TABLE cards (
+    id integer primary key, nid integer not null, did integer not null, ord integer not null,
+    mod integer not null, usn integer not null, type integer not null, queue integer not null,
+    due integer not null, ivl integer not null, factor integer not null, reps integer not null,
+    lapses integer not null, left integer not null, odue integer not null, odid integer not null,
+    flags integer not null, data text not null
+);
+CREATE TABLE revlog (
+    id integer primary key, cid integer not null, usn integer not null, ease integer not null,
+    ivl integer not null, lastIvl integer not null, factor integer not null, time integer not null,
+    type integer not null
+);
+CREATE TABLE graves (usn integer not null, oid integer not null, type integer not null);
+CREATE INDEX ix_notes_usn on notes (usn);
+CREATE INDEX ix_cards_usn on cards (usn);
+CREATE INDEX ix_revlog_usn on revlog (usn);
+CREATE INDEX ix_cards_nid on cards (nid);
+CREATE INDEX ix_cards_sched on cards (did, queue, due);
+CREATE INDEX ix_revlog_cid on revlog (cid);
+CREATE INDEX ix_notes_csum on notes (csum);
+";
+
+const COLLECTION_CONFIG: &str = r#"{"nextPos": 1, "estTimes": true, "activeDecks": [1], "sortType": "noteFld", "timeLim": 0, "sortBackwards": false, "addToCur": true, "curDeck": 1, "newBury": true, "newSpread": 0, "dueCounts": true, "curModel": null, "collapseTime": 1200}"#;
+
+const DECK_CONFIG: &str = r#"{"1": {"id": 1, "name": "Default", "replayq": true, "lapse": {"delays": [10], "mult": 0, "minInt": 1, "leechFails": 8, "leechAction": 0}, "rev": {"perDay": 200, "ease4": 1.3, "fuzz": 0.05, "minSpace": 1, "ivlFct": 1, "maxIvl": 36500, "bury": true}, "timer": 0, "maxTaken": 60, "usn": 0, "new": {"delays": [1, 10], "ints": [1, 4, 7], "initialFactor": 2500, "order": 1, "perDay": 20, "bury": true, "separate": true}, "mod": 0, "autoplay": true, "dyn": false}}"#;
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{Cursor, Read};
+
+    use rusqlite::Connection;
+
+    use crate::anki::{cloze, export_anki, CardType, TempFile};
+    use crate::model::{Book, Highlight, HighlightLocation, Note};
+
+    fn library() -> HashMap<String, Book> {
+        let mut book = Book::new("A Book".to_string(), "Doe, Jane".to_string());
+        book.add_highlight(Highlight::new(
+            3,
+            HighlightLocation::new(40, 42),
+            "The Top 5% go granular".to_string(),
+        ));
+        book.add_highlight(Highlight::new(
+            4,
+            HighlightLocation::new(50, 51),
+            "Half-heartedness doesn’t reach into majesty".to_string(),
+        ));
+        book.add_note(Note::new(3, 41, "Detail matters".to_string()));
+
+        let mut library = HashMap::new();
+        library.insert(book.title().to_string(), book);
+        library
+    }
+
+    /// unzip the package and return the guid and fields of each note
+    fn notes(card_type: CardType) -> Vec<(String, String)> {
+        let mut out = Cursor::new(Vec::new());
+        export_anki(&library(), card_type, "Highlights", &mut out).unwrap();
+
+        let mut zip = zip::ZipArchive::new(out).unwrap();
+        assert_eq!("{}", {
+            let mut media = String::new();
+            zip.by_name("media")
+                .unwrap()
+                .read_to_string(&mut media)
+                .unwrap();
+            media
+        });
+
+        let mut db = Vec::new();
+        zip.by_name("collection.anki2")
+            .unwrap()
+            .read_to_end(&mut db)
+            .unwrap();
+
+        let file = TempFile::new("anki2");
+        std::fs::write(&file.0, db).unwrap();
+        let conn = Connection::open(&file.0).unwrap();
+
+        let cards: i64 = conn
+            .query_row("SELECT COUNT(*) FROM cards", [], |r| r.get(0))
+            .unwrap();
+
+        let mut stmt = conn
+            .prepare("SELECT guid, flds FROM notes ORDER BY sfld")
+            .unwrap();
+        let notes: Vec<(String, String)> = stmt
+            .query_map([], |r| Ok((r.get(0)?, r.get(1)?)))
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+
+        assert_eq!(cards as usize, notes.len());
+        notes
+    }
+
+    #[test]
+    fn cloze_cards() {
+        let notes = notes(CardType::Cloze);
+        assert_eq!(2, notes.len());
+        assert!(notes[0].1.starts_with("The Top 5% go {{c1::granular}}\x1f"));
+        assert!(notes[0].1.ends_with("\x1fDetail matters"));
+        assert!(notes[1].1.starts_with("{{c1::Half-heartedness}} doesn’t"));
+    }
+
+    #[test]
+    fn note_quote_cards() {
+        let notes = notes(CardType::NoteQuote);
+        assert_eq!(1, notes.len());
+        assert!(notes[0]
+            .1
+            .starts_with("Detail matters\x1fThe Top 5% go granular"));
+    }
+
+    #[test]
+    fn stable_guids() {
+        assert_eq!(notes(CardType::Cloze), notes(CardType::Cloze));
+        assert_ne!(notes(CardType::Cloze)[0].0, notes(CardType::NoteQuote)[0].0);
+    }
+
+    #[test]
+    fn cloze_text() {
+        assert_eq!(
+            "The Top 5% go {{c1::granular}}",
+            cloze("The Top 5% go granular")
+        );
+        assert_eq!("{{c1::a &lt; b}}", cloze("a < b"));
+    }
+}
@@ -0,0 +1,213 @@
+//! Atomic, Zettelkasten style, output: a note per highlight rather than a file per book.
+//!
+//! Each highlight note's front matter links back to an index note for its book, and the index
+//! note links out to every highlight note. Highlight notes are named by a Tera template, see
+//! [DEFAULT_NOTE_NAME].
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+use tera::{Context, Tera};
+
+use crate::filename::sanitise;
+use crate::model::Book;
+use crate::output::{
+    base_context, load_templates, slugify, template_name, tera_error, write_atomically,
+    HighlightView, RenderError,
+};
+
+/// Names each highlight note by when it was highlighted and its first few words.
+///
+/// Name templates can use `id`, `timestamp` (`YYYYMMDDhhmmss`, when the highlight was made or
+/// empty if the source didn't record it), `slug` (the first words of the quote), `page`,
+/// `location`, `title` and `author`.
+pub const DEFAULT_NOTE_NAME: &str = "{{ timestamp }} {{ slug }}";
+
+/// how many words of the quote make up its `slug`
+const SLUG_WORDS: usize = 6;
+
+#[derive(Serialize)]
+struct HighlightNote<'a> {
+    name: String,
+    #[serde(flatten)]
+    highlight: HighlightView<'a>,
+}
+
+/// Writes a note per highlight in the [Book] to the output [Path], named with the `note_name`
+/// template, along with an index note for the book that links to each of them.
+///
+/// A custom template, if provided, is used for the highlight notes in place of the default. A
+/// template directory can provide `atomic/note.md` for the highlight notes and `atomic/index.md`
+/// for the index, either of which falls back to the default if it's missing.
+pub fn render_atomic(
+    book: &Book,
+    template: &Option<PathBuf>,
+    note_name: &str,
+    output_dir: &Path,
+) -> Result<(), RenderError> {
+    let (mut tera, note_template) = load_templates(template, "atomic/note.md")?;
+    let index_template = template_name(&tera, "atomic/index.md");
+    tera.add_raw_template("note_name", note_name)
+        .map_err(|e| RenderError::ParsingFailed(tera_error(&e)))?;
+
+    fs::create_dir_all(output_dir)
+        .map_err(|e| RenderError::CreateOutputFileFailed(e.to_string()))?;
+
+    let index = file_stem(&format!("{}. {}", book.author(), book.title()));
+    let mut used: HashSet<String> = HashSet::from([index.clone()]);
+
+    let mut ctx = base_context(book);
+    ctx.insert("index", &index);
+
+    let mut notes: Vec<HighlightNote> = Vec::new();
+
+    for (location, highlight) in book.highlights().iter() {
+        let view = HighlightView::new(book, location, highlight);
+
+        let mut name_ctx = ctx.clone();
+        name_ctx.insert("id", &view.id());
+        name_ctx.insert(
+            "timestamp",
+            // left empty rather than now, so re-running doesn't give the note a new name
+            &highlight
+                .date()
+                .map(|d| d.format("%Y%m%d%H%M%S").to_string())
+                .unwrap_or_default(),
+        );
+        name_ctx.insert(
+            "slug",
+            &slugify(
+                &highlight
+                    .quote()
+                    .split_whitespace()
+                    .take(SLUG_WORDS)
+                    .collect::<Vec<&str>>()
+                    .join(" "),
+            ),
+        );
+        name_ctx.insert("page", highlight.page());
+        name_ctx.insert("location", &location.start());
+
+        let name = tera
+            .render("note_name", &name_ctx)
+            .map_err(|e| RenderError::ParsingFailed(tera_error(&e)))?;
+        let name = unique_name(file_stem(&name), &mut used);
+
+        let note = HighlightNote {
+            name,
+            highlight: view,
+        };
+
+        ctx.insert("name", &note.name);
+        ctx.insert("highlight", &note);
+        render_to_file(
+            &tera,
+            &note_template,
+            &ctx,
+            &output_dir.join(format!("{}.md", note.name)),
+        )?;
+
+        notes.push(note);
+    }
+
+    ctx.remove("name");
+    ctx.remove("highlight");
+    ctx.insert("notes", &notes);
+    render_to_file(
+        &tera,
+        &index_template,
+        &ctx,
+        &output_dir.join(format!("{}.md", index)),
+    )
+}
+
+fn render_to_file(
+    tera: &Tera,
+    template: &str,
+    ctx: &Context,
+    file_path: &Path,
+) -> Result<(), RenderError> {
+    write_atomically(file_path, false, |writer| {
+        tera.render_to(template, ctx, writer)
+            .map_err(|e| RenderError::ParsingFailed(tera_error(&e)))
+    })
+}
+
+/// a note name that's safe as a file name and as the target of a wikilink
+fn file_stem(name: &str) -> String {
+    sanitise(&name.replace(['/', '#', '^', '[', ']'], "-"))
+}
+
+/// the name, or the name with a number after it, whichever isn't already in `used`
+fn unique_name(name: String, used: &mut HashSet<String>) -> String {
+    let mut unique = name.clone();
+    let mut i = 2;
+    while !used.insert(unique.clone()) {
+        unique = format!("{} {}", name, i);
+        i += 1;
+    }
+
+    unique
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use chrono::NaiveDateTime;
+
+    use crate::atomic::{render_atomic, DEFAULT_NOTE_NAME};
+    use crate::model::{Book, Highlight, HighlightLocation, Note};
+    use crate::output::highlight_id;
+
+    #[test]
+    fn notes_and_index() {
+        let mut book = Book::new("A Book: Subtitled".to_string(), "Doe, Jane".to_string());
+        let mut first = Highlight::new(
+            3,
+            HighlightLocation::new(40, 42),
+            "The first idea, in a few more words than six".to_string(),
+        );
+        first.add_date(
+            NaiveDateTime::parse_from_str("2023-08-21 22:14:52", "%Y-%m-%d %H:%M:%S").unwrap(),
+        );
+        book.add_highlight(first);
+        book.add_note(Note::new(3, 41, "A note".to_string()));
+        book.add_highlight(Highlight::new(
+            4,
+            HighlightLocation::new(50, 50),
+            "Second".to_string(),
+        ));
+        book.set_source("pdf");
+
+        let dir = env::temp_dir().join(format!("ktr-atomic-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        render_atomic(&book, &None, DEFAULT_NOTE_NAME, &dir).unwrap();
+        render_atomic(&book, &None, DEFAULT_NOTE_NAME, &dir).unwrap();
+        // a note, an index and the undated highlight's note, named the same on every run
+        let notes = fs::read_dir(&dir).unwrap().count();
+        let undated = dir.join("second.md").exists();
+        render_atomic(&book, &None, "{{ location }}", &dir).unwrap();
+
+        let first_name = "20230821221452 the-first-idea-in-a-few";
+        let note = fs::read_to_string(dir.join(format!("{}.md", first_name))).unwrap();
+        let index = fs::read_to_string(dir.join("Doe, Jane. A Book- Subtitled.md")).unwrap();
+        let by_location = fs::read_to_string(dir.join("50.md")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let id = highlight_id(&book, &HighlightLocation::new(40, 42));
+        assert!(note.contains(&format!("id: {}\n", id)));
+        assert!(note.contains("book: \"[[Doe, Jane. A Book- Subtitled]]\"\n"));
+        assert!(note.contains("type: pdf\n"));
+        assert!(index.contains("type: pdf\n"));
+        assert!(note.contains("> The first idea, in a few more words than six\n\nA note"));
+
+        assert_eq!(3, notes);
+        assert!(undated);
+        assert!(by_location.contains("> Second"));
+        assert!(index.contains("- [[40]]\n- [[50]]\n"));
+    }
+}
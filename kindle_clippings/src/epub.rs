@@ -0,0 +1,423 @@
+//! EPUB export, to carry highlights back onto an e-reader.
+//!
+//! Each book becomes a chapter, with its highlights as blockquotes and any notes as asides. The
+//! package follows the EPUB 3 container layout: an uncompressed `mimetype` entry first, then
+//! `META-INF/container.xml` pointing at the package document, which lists a navigation document,
+//! a stylesheet and the chapters.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{Seek, Write};
+use std::path::Path;
+
+use chrono::Utc;
+use uuid::Uuid;
+use zip::write::SimpleFileOptions;
+use zip::{CompressionMethod, ZipWriter};
+
+use crate::model::{Book, Highlight};
+use crate::output::{has_locations, unique_file_name, RenderError};
+
+/// name of the file written by [render_epub] when the library is combined
+pub const COMMONPLACE_FILE_NAME: &str = "commonplace.epub";
+
+/// title of the combined EPUB written by [render_epub]
+pub const COMMONPLACE_TITLE: &str = "Commonplace Book";
+
+const CONTAINER: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<container version="1.0" xmlns="urn:oasis:names:tc:opendocument:xmlns:container">
+  <rootfiles>
+    <rootfile full-path="OEBPS/content.opf" media-type="application/oebps-package+xml"/>
+  </rootfiles>
+</container>
+"#;
+
+const STYLE: &str = "\
+h1 { margin-bottom: 0.2em; }
+p.author { margin-top: 0; font-style: italic; }
+blockquote { margin: 1.5em 0 0 0; padding-left: 1em; border-left: 3px solid #888; }
+blockquote p { margin: 0.3em 0; }
+p.location { font-size: 0.8em; color: #666; }
+aside { margin: 0.5em 0 0 1em; font-style: italic; }
+";
+
+/// Writes the library to EPUB files in the output [Path]. Either one EPUB per book, named after
+/// its title, or when `combined` a single `commonplace.epub` with a chapter per book.
+pub fn render_epub(
+    library: &HashMap<String, Book>,
+    output_dir: &Path,
+    combined: bool,
+) -> Result<(), RenderError> {
+    let mut books: Vec<&Book> = library.values().collect();
+    books.sort_by(|a, b| a.title().cmp(b.title()));
+
+    if combined {
+        return write_epub(
+            &books,
+            COMMONPLACE_TITLE,
+            &output_dir.join(COMMONPLACE_FILE_NAME),
+        );
+    }
+
+    let mut used: HashSet<String> = HashSet::new();
+    books.iter().try_for_each(|book| {
+        let file_name = unique_file_name(book.title(), "epub", &mut used);
+        write_epub(&[book], book.title(), &output_dir.join(file_name))
+    })
+}
+
+fn write_epub(books: &[&Book], title: &str, file_path: &Path) -> Result<(), RenderError> {
+    let file: File = match File::create(file_path) {
+        Ok(f) => f,
+        Err(e) => return Err(RenderError::CreateOutputFileFailed(e.to_string())),
+    };
+
+    export_epub(books, title, file)
+}
+
+/// Writes an EPUB titled `title` to `writer`, with a chapter for each of the books in the order
+/// given.
+///
+/// The identifier is derived from the books' titles and authors, so a re-export replaces the
+/// copy already on a reader rather than sitting alongside it.
+pub fn export_epub<W>(books: &[&Book], title: &str, writer: W) -> Result<(), RenderError>
+where
+    W: Write + Seek,
+{
+    let to_error = |e: zip::result::ZipError| RenderError::WriteFailed(e.to_string());
+    let write_error = |e: std::io::Error| RenderError::WriteFailed(e.to_string());
+
+    let mut zip = ZipWriter::new(writer);
+
+    // readers find out what they're looking at from the first bytes of the file, so this entry
+    // has to come first and can't be compressed
+    let stored = SimpleFileOptions::default().compression_method(CompressionMethod::Stored);
+    zip.start_file("mimetype", stored).map_err(to_error)?;
+    zip.write_all(b"application/epub+zip")
+        .map_err(write_error)?;
+
+    let options = SimpleFileOptions::default().compression_method(CompressionMethod::Deflated);
+    let mut add = |name: &str, content: &str| -> Result<(), RenderError> {
+        zip.start_file(name, options).map_err(to_error)?;
+        zip.write_all(content.as_bytes()).map_err(write_error)
+    };
+
+    add("META-INF/container.xml", CONTAINER)?;
+    add("OEBPS/content.opf", &package(books, title))?;
+    add("OEBPS/nav.xhtml", &navigation(books, title))?;
+    add("OEBPS/style.css", STYLE)?;
+    for (i, book) in books.iter().enumerate() {
+        add(&format!("OEBPS/{}", chapter_file(i)), &chapter(book))?;
+    }
+
+    zip.finish().map_err(to_error)?;
+    Ok(())
+}
+
+fn chapter_file(index: usize) -> String {
+    format!("chapter-{}.xhtml", index + 1)
+}
+
+/// the package document, listing everything in the EPUB and the reading order
+fn package(books: &[&Book], title: &str) -> String {
+    let mut seed = title.to_string();
+    books
+        .iter()
+        .for_each(|b| seed.push_str(&format!("\n{}\n{}", b.title(), b.author())));
+    let identifier = Uuid::new_v5(&Uuid::NAMESPACE_OID, seed.as_bytes());
+
+    let mut authors: Vec<&str> = books.iter().map(|b| b.author()).collect();
+    authors.sort();
+    authors.dedup();
+
+    let mut opf = String::new();
+    opf.push_str(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<package xmlns="http://www.idpf.org/2007/opf" version="3.0" unique-identifier="book-id" xml:lang="en">
+  <metadata xmlns:dc="http://purl.org/dc/elements/1.1/">
+"#,
+    );
+    let _ = writeln!(
+        opf,
+        "    <dc:identifier id=\"book-id\">urn:uuid:{}</dc:identifier>",
+        identifier
+    );
+    let _ = writeln!(opf, "    <dc:title>{}</dc:title>", escape(title));
+    authors
+        .iter()
+        .filter(|a| !a.trim().is_empty())
+        .for_each(|a| {
+            let _ = writeln!(opf, "    <dc:creator>{}</dc:creator>", escape(a));
+        });
+    opf.push_str("    <dc:language>en</dc:language>\n");
+    let _ = writeln!(
+        opf,
+        "    <meta property=\"dcterms:modified\">{}</meta>",
+        Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+    );
+    let _ = writeln!(
+        opf,
+        "    <meta name=\"generator\" content=\"ktr {}\"/>",
+        env!("CARGO_PKG_VERSION")
+    );
+    opf.push_str(
+        r#"  </metadata>
+  <manifest>
+    <item id="nav" href="nav.xhtml" media-type="application/xhtml+xml" properties="nav"/>
+    <item id="style" href="style.css" media-type="text/css"/>
+"#,
+    );
+    for i in 0..books.len() {
+        let _ = writeln!(
+            opf,
+            "    <item id=\"chapter-{}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>",
+            i + 1,
+            chapter_file(i)
+        );
+    }
+    opf.push_str("  </manifest>\n  <spine>\n");
+    for i in 0..books.len() {
+        let _ = writeln!(opf, "    <itemref idref=\"chapter-{}\"/>", i + 1);
+    }
+    opf.push_str("  </spine>\n</package>\n");
+
+    opf
+}
+
+/// the table of contents
+fn navigation(books: &[&Book], title: &str) -> String {
+    let mut body = String::from("<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n<ol>\n");
+    for (i, book) in books.iter().enumerate() {
+        let _ = writeln!(
+            body,
+            "<li><a href=\"{}\">{}</a></li>",
+            chapter_file(i),
+            escape(book.title())
+        );
+    }
+    body.push_str("</ol>\n</nav>\n");
+
+    xhtml(title, &body)
+}
+
+fn chapter(book: &Book) -> String {
+    let mut body = String::from("<section epub:type=\"chapter\">\n");
+    let _ = writeln!(body, "<h1>{}</h1>", escape(book.title()));
+    if !book.author().trim().is_empty() {
+        let _ = writeln!(body, "<p class=\"author\">{}</p>", escape(book.author()));
+    }
+
+    for highlight in book.highlights().values() {
+        body.push_str(&highlight_block(highlight, has_locations(book)));
+    }
+    body.push_str("</section>\n");
+
+    xhtml(book.title(), &body)
+}
+
+fn highlight_block(highlight: &Highlight, has_locations: bool) -> String {
+    let mut block = String::from("<blockquote>\n");
+    highlight
+        .quote()
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .for_each(|l| {
+            let _ = writeln!(block, "<p>{}</p>", escape(l));
+        });
+
+    let location = highlight.location();
+    let mut cite = match location.start() == location.end() {
+        true => format!("Location {}", location.start()),
+        false => format!("Location {}-{}", location.start(), location.end()),
+    };
+    if !has_locations {
+        cite = format!("Page {}", highlight.page());
+    } else if *highlight.page() > 0 {
+        cite = format!("Page {}, {}", highlight.page(), cite.to_lowercase());
+    }
+    let _ = writeln!(block, "<p class=\"location\">{}</p>", cite);
+    block.push_str("</blockquote>\n");
+
+    if let Some(note) = highlight.note() {
+        let _ = writeln!(block, "<aside>{}</aside>", escape(note.content()));
+    }
+
+    block
+}
+
+fn xhtml(title: &str, body: &str) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE html>
+<html xmlns="http://www.w3.org/1999/xhtml" xmlns:epub="http://www.idpf.org/2007/ops" lang="en" xml:lang="en">
+<head>
+<meta charset="UTF-8"/>
+<title>{}</title>
+<link rel="stylesheet" type="text/css" href="style.css"/>
+</head>
+<body>
+{}</body>
+</html>
+"#,
+        escape(title),
+        body
+    )
+}
+
+/// escape text for XML, dropping the control characters XML 1.0 doesn't allow
+fn escape(s: &str) -> String {
+    s.chars()
+        .filter(|c| !c.is_control() || matches!(c, '\t' | '\n' | '\r'))
+        .fold(String::with_capacity(s.len()), |mut out, c| {
+            match c {
+                '&' => out.push_str("&amp;"),
+                '<' => out.push_str("&lt;"),
+                '>' => out.push_str("&gt;"),
+                '"' => out.push_str("&quot;"),
+                '\'' => out.push_str("&apos;"),
+                c => out.push(c),
+            }
+            out
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use crate::epub::export_epub;
+    use crate::model::{Book, Highlight, HighlightLocation, Note};
+
+    fn books() -> Vec<Book> {
+        let mut first = Book::new(
+            "The 5 AM Club: Own Your Morning.".to_string(),
+            "Sharma, Robin".to_string(),
+        );
+        first.add_highlight(Highlight::new(
+            3,
+            HighlightLocation::new(40, 42),
+            "Fish & chips <are> \"great\"\u{0}".to_string(),
+        ));
+        first.add_note(Note::new(3, 41, "A note".to_string()));
+
+        let mut second = Book::new("Atomic Habits".to_string(), "Clear, James".to_string());
+        second.add_highlight(Highlight::new(
+            0,
+            HighlightLocation::new(7, 7),
+            "Small habits".to_string(),
+        ));
+
+        vec![first, second]
+    }
+
+    fn entry(zip: &mut zip::ZipArchive<Cursor<Vec<u8>>>, name: &str) -> String {
+        let mut content = String::new();
+        zip.by_name(name)
+            .unwrap_or_else(|_| panic!("{} is missing", name))
+            .read_to_string(&mut content)
+            .unwrap();
+        content
+    }
+
+    /// check the package the way epubcheck would, returning the chapters in spine order
+    fn validate(bytes: Vec<u8>) -> Vec<String> {
+        // the mimetype must be the first entry, stored, with no extra field
+        assert_eq!(b"PK\x03\x04", &bytes[0..4]);
+        assert_eq!([0, 0], bytes[8..10], "mimetype is compressed");
+        assert_eq!([0, 0], bytes[28..30], "mimetype has an extra field");
+        assert_eq!(b"mimetypeapplication/epub+zip", &bytes[30..58]);
+
+        let mut zip = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+        assert_eq!("mimetype", zip.by_index(0).unwrap().name());
+
+        let container = entry(&mut zip, "META-INF/container.xml");
+        let container = roxmltree::Document::parse(&container).unwrap();
+        let opf_path = container
+            .descendants()
+            .find(|n| n.has_tag_name("rootfile"))
+            .and_then(|n| n.attribute("full-path"))
+            .unwrap()
+            .to_string();
+
+        let opf = entry(&mut zip, &opf_path);
+        let opf = roxmltree::Document::parse(&opf).unwrap();
+        let package = opf.root_element();
+        assert_eq!(Some("3.0"), package.attribute("version"));
+
+        let id = package.attribute("unique-identifier").unwrap();
+        let identifier = opf
+            .descendants()
+            .find(|n| n.has_tag_name("identifier") && n.attribute("id") == Some(id))
+            .unwrap();
+        assert!(identifier.text().unwrap().starts_with("urn:uuid:"));
+        for required in ["title", "language"] {
+            assert!(opf.descendants().any(|n| n.has_tag_name(required)));
+        }
+        assert!(opf
+            .descendants()
+            .any(|n| n.attribute("property") == Some("dcterms:modified")));
+
+        // every manifest item exists and every content document is well formed
+        let items: Vec<(String, String, String)> = opf
+            .descendants()
+            .filter(|n| n.has_tag_name("item"))
+            .map(|n| {
+                (
+                    n.attribute("id").unwrap().to_string(),
+                    format!("OEBPS/{}", n.attribute("href").unwrap()),
+                    n.attribute("properties").unwrap_or("").to_string(),
+                )
+            })
+            .collect();
+        assert_eq!(1, items.iter().filter(|i| i.2 == "nav").count());
+        for (_, path, _) in items.iter() {
+            let content = entry(&mut zip, path);
+            if path.ends_with(".xhtml") {
+                // content documents carry the HTML5 doctype
+                let options = roxmltree::ParsingOptions {
+                    allow_dtd: true,
+                    ..Default::default()
+                };
+                roxmltree::Document::parse_with_options(&content, options)
+                    .unwrap_or_else(|e| panic!("{} isn't well formed: {}", path, e));
+            }
+        }
+
+        opf.descendants()
+            .filter(|n| n.has_tag_name("itemref"))
+            .map(|n| {
+                let idref = n.attribute("idref").unwrap();
+                let item = items.iter().find(|i| i.0 == idref).unwrap();
+                entry(&mut zip, &item.1)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn single_book() {
+        let books = books();
+        let mut out = Cursor::new(Vec::new());
+        export_epub(&[&books[0]], books[0].title(), &mut out).unwrap();
+
+        let chapters = validate(out.into_inner());
+        assert_eq!(1, chapters.len());
+        assert!(chapters[0].contains("<p>Fish &amp; chips &lt;are&gt; &quot;great&quot;</p>"));
+        assert!(chapters[0].contains("<p class=\"location\">Page 3, location 40-42</p>"));
+        assert!(chapters[0].contains("<aside>A note</aside>"));
+    }
+
+    #[test]
+    fn commonplace_book() {
+        let books = books();
+        let mut out = Cursor::new(Vec::new());
+        export_epub(&[&books[1], &books[0]], "Commonplace Book", &mut out).unwrap();
+
+        let chapters = validate(out.into_inner());
+        assert_eq!(2, chapters.len());
+        assert!(chapters[0].contains("<h1>Atomic Habits</h1>"));
+        assert!(chapters[0].contains("<p class=\"location\">Location 7</p>"));
+        assert!(chapters[1].contains("<h1>The 5 AM Club: Own Your Morning.</h1>"));
+    }
+}
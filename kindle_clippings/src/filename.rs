@@ -0,0 +1,188 @@
+//! Output file names built from a Tera template, made safe to use on any file system.
+//!
+//! Templates can use `title`, `author` and `id`, and may contain `/` to lay notes out in
+//! subdirectories, for example `{{ author }}/{{ title }}`. A `/` in a title or author never starts
+//! a new directory, and `..` can't climb out of the output directory.
+//!
+//! Each part of the path is sanitised: characters reserved on Windows, macOS or Linux are
+//! replaced, names Windows keeps for devices are suffixed with `_`, long names are cut to a length
+//! every file system accepts, and unicode is normalised to NFC so the same title always gives the
+//! same name.
+
+use std::path::PathBuf;
+
+use tera::{Context, Tera};
+use unicode_normalization::UnicodeNormalization;
+
+use crate::filters;
+use crate::model::Book;
+use crate::output::{book_id, RenderError};
+
+/// Names each note after its book's author and title, the original naming.
+pub const DEFAULT_FILE_NAME: &str = "{{ author }}. {{ title }}";
+
+/// longest a single part of a path can be, in bytes, leaving room under the usual 255 byte limit
+/// for an extension
+const MAX_NAME_BYTES: usize = 200;
+
+/// names Windows reserves for devices, whatever their extension
+const WINDOWS_RESERVED: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// A compiled file name template, see the [module documentation](self).
+pub struct FileNameTemplate {
+    tera: Tera,
+}
+
+impl FileNameTemplate {
+    pub fn new(template: &str) -> Result<Self, RenderError> {
+        let mut tera = Tera::default();
+        filters::register(&mut tera);
+        tera.add_raw_template("file_name", template)
+            .map_err(|e| RenderError::ParsingFailed(e.to_string()))?;
+
+        Ok(FileNameTemplate { tera })
+    }
+
+    /// the path of the book's file, relative to the output directory
+    pub fn path(&self, book: &Book, extension: &str) -> Result<PathBuf, RenderError> {
+        let mut ctx = Context::new();
+        ctx.insert("title", &flatten(book.title()));
+        ctx.insert("author", &flatten(book.author()));
+        ctx.insert("id", &book_id(book).to_string());
+
+        let rendered = self
+            .tera
+            .render("file_name", &ctx)
+            .map_err(|e| RenderError::ParsingFailed(e.to_string()))?;
+
+        let mut parts: Vec<String> = rendered
+            .split('/')
+            .filter(|p| is_named_segment(p))
+            .map(sanitise)
+            .collect();
+
+        let file_name = match parts.pop() {
+            Some(name) => format!("{}.{}", name, extension),
+            None => format!("{}.{}", sanitise(""), extension),
+        };
+
+        Ok(parts.iter().collect::<PathBuf>().join(file_name))
+    }
+}
+
+/// whether a part of a path names a directory or file, rather than being empty, `.` or `..`, which
+/// are dropped so the path can't climb out of the output directory
+pub(crate) fn is_named_segment(part: &str) -> bool {
+    !matches!(part.trim(), "" | "." | "..")
+}
+
+/// keep a value from adding directories to the path
+fn flatten(value: &str) -> String {
+    value.replace(['/', '\\'], "-")
+}
+
+/// make a single file or directory name safe to use on any file system
+pub fn sanitise(name: &str) -> String {
+    let name: String = name
+        .nfc()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '-',
+            c if c.is_control() => ' ',
+            c => c,
+        })
+        .collect();
+
+    let mut name = truncate(name.trim(), MAX_NAME_BYTES)
+        // Windows drops these from the end of a name, so two names could end up the same
+        .trim_end_matches(['.', ' '])
+        .to_string();
+
+    if name.is_empty() {
+        return "untitled".to_string();
+    }
+
+    let base_len = name.find('.').unwrap_or(name.len());
+    if WINDOWS_RESERVED
+        .iter()
+        .any(|r| r.eq_ignore_ascii_case(name[..base_len].trim_end()))
+    {
+        name.insert(base_len, '_');
+    }
+
+    name
+}
+
+/// the longest prefix of `s` that fits in `max` bytes without splitting a character
+fn truncate(s: &str, max: usize) -> &str {
+    if s.len() <= max {
+        return s;
+    }
+
+    let end = (0..=max)
+        .rev()
+        .find(|i| s.is_char_boundary(*i))
+        .unwrap_or(0);
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use crate::filename::{sanitise, FileNameTemplate, DEFAULT_FILE_NAME};
+    use crate::model::Book;
+
+    #[test]
+    fn sanitising() {
+        assert_eq!("Why- A Story- Part 1-2", sanitise("Why? A Story: Part 1/2"));
+        assert_eq!("con_", sanitise("con"));
+        assert_eq!("aux_.notes", sanitise("aux.notes"));
+        assert_eq!("Console", sanitise("Console"));
+        assert_eq!("untitled", sanitise(".."));
+        assert_eq!("The End", sanitise("The End... "));
+        // a decomposed é becomes the single composed character
+        assert_eq!("Caf\u{e9}", sanitise("Cafe\u{301}"));
+
+        let long = "ü".repeat(150);
+        let cut = sanitise(&long);
+        assert!(cut.len() <= 200);
+        assert!(cut.chars().all(|c| c == 'ü'));
+    }
+
+    #[test]
+    fn templates() {
+        let book = Book::new(
+            "AC/DC: The Story?".to_string(),
+            "../../Doe, Jane".to_string(),
+        );
+
+        let default = FileNameTemplate::new(DEFAULT_FILE_NAME).unwrap();
+        assert_eq!(
+            PathBuf::from("..-..-Doe, Jane. AC-DC- The Story-.md"),
+            default.path(&book, "md").unwrap()
+        );
+
+        let nested = FileNameTemplate::new("/{{ author }}/{{ title }}").unwrap();
+        assert_eq!(
+            PathBuf::from("..-..-Doe, Jane/AC-DC- The Story-.org"),
+            nested.path(&book, "org").unwrap()
+        );
+
+        let parent = FileNameTemplate::new("../{{ title }}").unwrap();
+        assert_eq!(
+            PathBuf::from("AC-DC- The Story-.md"),
+            parent.path(&book, "md").unwrap()
+        );
+
+        let current = FileNameTemplate::new("./Books/ . /../{{ title }}/..").unwrap();
+        assert_eq!(
+            PathBuf::from("Books/AC-DC- The Story-.md"),
+            current.path(&book, "md").unwrap()
+        );
+
+        assert!(FileNameTemplate::new("{{ title").is_err());
+    }
+}
@@ -0,0 +1,317 @@
+//! Filters and functions for writing notes, registered on every template KTR renders a book with.
+//!
+//! | Name | Kind | Use |
+//! | ---- | ---- | --- |
+//! | `slugify` | filter | `{{ title \| slugify }}`, lowercase and hyphen separated |
+//! | `wikilink` | filter | `{{ title \| wikilink }}` or `{{ title \| wikilink(alias="Book") }}` |
+//! | `title_case` | filter | `{{ title \| title_case }}` |
+//! | `blockquote` | filter | `{{ hl.quote \| blockquote }}`, a `> ` before every line |
+//! | `truncate_words` | filter | `{{ hl.quote \| truncate_words(count=10, end="...") }}` |
+//! | `format_date` | filter | `{{ hl.date \| format_date(format="%d %B %Y", timezone="+01:00") }}` |
+//! | `now` | function | `{{ now(format="%Y-%m-%d %H:%M", timezone="local") }}` |
+//!
+//! `format` is a [chrono format string](https://docs.rs/chrono/latest/chrono/format/strftime/index.html),
+//! `%Y-%m-%d` if it's left out, or `rfc3339` for a full timestamp. `timezone` can be `UTC`, the default, `local` for the computer's
+//! own timezone, or an offset such as `+05:30`. Dates without a timezone, like those on Kindle
+//! highlights, are taken to be in UTC.
+
+use std::collections::HashMap;
+
+use chrono::{
+    DateTime, FixedOffset, Local, NaiveDate, NaiveDateTime, SecondsFormat, TimeZone, Utc,
+};
+use tera::{try_get_value, Error, Tera, Value};
+
+use crate::output::slugify;
+
+/// words left lowercase by [title_case] unless they start or end the title
+const MINOR_WORDS: [&str; 16] = [
+    "a", "an", "and", "as", "at", "but", "by", "for", "in", "nor", "of", "on", "or", "the", "to",
+    "with",
+];
+
+/// add KTR's filters and functions to the [Tera] instance
+pub(crate) fn register(tera: &mut Tera) {
+    tera.register_filter("slugify", slugify_filter);
+    tera.register_filter("wikilink", wikilink);
+    tera.register_filter("title_case", title_case);
+    tera.register_filter("blockquote", blockquote);
+    tera.register_filter("truncate_words", truncate_words);
+    tera.register_filter("format_date", format_date);
+    tera.register_function("now", now);
+}
+
+fn slugify_filter(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let s = try_get_value!("slugify", "value", String, value);
+    Ok(Value::String(slugify(&s)))
+}
+
+/// `[[value]]`, or `[[value|alias]]`, with the characters that would break the link replaced
+fn wikilink(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let target = try_get_value!("wikilink", "value", String, value);
+    let target = target.replace(['[', ']', '|', '#', '^'], "-");
+
+    match args.get("alias") {
+        Some(alias) => {
+            let alias = try_get_value!("wikilink", "alias", String, alias);
+            Ok(Value::String(format!(
+                "[[{}|{}]]",
+                target,
+                alias.replace(['[', ']'], "-")
+            )))
+        }
+        None => Ok(Value::String(format!("[[{}]]", target))),
+    }
+}
+
+/// each word capitalised, other than the minor words in the middle of the title
+fn title_case(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let s = try_get_value!("title_case", "value", String, value);
+    let words: Vec<&str> = s.split(' ').collect();
+    let last = words.len().saturating_sub(1);
+
+    let cased: Vec<String> = words
+        .iter()
+        .enumerate()
+        .map(|(i, word)| {
+            let lower = word.to_lowercase();
+            if i != 0 && i != last && MINOR_WORDS.contains(&lower.as_str()) {
+                return lower;
+            }
+
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        })
+        .collect();
+
+    Ok(Value::String(cased.join(" ")))
+}
+
+/// a markdown blockquote, with every line of the value quoted
+fn blockquote(value: &Value, _: &HashMap<String, Value>) -> tera::Result<Value> {
+    let s = try_get_value!("blockquote", "value", String, value);
+
+    let quoted: Vec<String> = s
+        .trim_end()
+        .lines()
+        .map(|line| match line.is_empty() {
+            true => ">".to_string(),
+            false => format!("> {}", line),
+        })
+        .collect();
+
+    Ok(Value::String(quoted.join("\n")))
+}
+
+/// the first `count` words, followed by `end` if any were cut
+fn truncate_words(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let s = try_get_value!("truncate_words", "value", String, value);
+    let count = match args.get("count") {
+        Some(c) => try_get_value!("truncate_words", "count", usize, c),
+        None => return Err(Error::msg("Filter `truncate_words` expected a `count`")),
+    };
+    let end = match args.get("end") {
+        Some(e) => try_get_value!("truncate_words", "end", String, e),
+        None => "…".to_string(),
+    };
+
+    let words: Vec<&str> = s.split_whitespace().collect();
+    if words.len() <= count {
+        return Ok(Value::String(s));
+    }
+
+    Ok(Value::String(format!(
+        "{}{}",
+        words[..count].join(" "),
+        end
+    )))
+}
+
+fn format_date(value: &Value, args: &HashMap<String, Value>) -> tera::Result<Value> {
+    let date: DateTime<FixedOffset> = match value {
+        Value::Number(n) => n
+            .as_i64()
+            .and_then(|secs| DateTime::from_timestamp(secs, 0))
+            .map(|d| d.fixed_offset()),
+        Value::String(s) => parse_date(s),
+        _ => None,
+    }
+    .ok_or_else(|| {
+        Error::msg(format!(
+            "Filter `format_date` can't read {} as a date",
+            value
+        ))
+    })?;
+
+    format_in(date, "format_date", args)
+}
+
+/// the time it is now
+fn now(args: &HashMap<String, Value>) -> tera::Result<Value> {
+    format_in(Utc::now().fixed_offset(), "now", args)
+}
+
+/// an RFC 3339 date and time, a date and time without a timezone, or a date on its own
+fn parse_date(s: &str) -> Option<DateTime<FixedOffset>> {
+    if let Ok(date) = DateTime::parse_from_rfc3339(s) {
+        return Some(date);
+    }
+
+    s.parse::<NaiveDateTime>()
+        .ok()
+        .or_else(|| {
+            s.parse::<NaiveDate>()
+                .ok()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+        })
+        .map(|d| Utc.from_utc_datetime(&d).fixed_offset())
+}
+
+/// the date in the `timezone` argument, formatted with the `format` argument
+fn format_in(
+    date: DateTime<FixedOffset>,
+    name: &str,
+    args: &HashMap<String, Value>,
+) -> tera::Result<Value> {
+    let format = match args.get("format") {
+        Some(f) => try_get_value!(name, "format", String, f),
+        None => "%Y-%m-%d".to_string(),
+    };
+    let timezone = match args.get("timezone") {
+        Some(t) => try_get_value!(name, "timezone", String, t),
+        None => "UTC".to_string(),
+    };
+
+    let date = match timezone.as_str() {
+        "UTC" | "utc" => date.with_timezone(&Utc).fixed_offset(),
+        "local" => date.with_timezone(&Local).fixed_offset(),
+        offset => match offset.parse::<FixedOffset>() {
+            Ok(tz) => date.with_timezone(&tz),
+            Err(_) => {
+                return Err(Error::msg(format!(
+                    "`{}` isn't a timezone, use UTC, local or an offset like +01:00",
+                    offset
+                )))
+            }
+        },
+    };
+
+    // an invalid format string panics while formatting, so check it first
+    let items: Vec<chrono::format::Item> = chrono::format::StrftimeItems::new(&format).collect();
+    if items.contains(&chrono::format::Item::Error) {
+        return Err(Error::msg(format!(
+            "`{}` isn't a valid date format",
+            format
+        )));
+    }
+
+    let formatted = match format.as_str() {
+        "rfc3339" => date.to_rfc3339_opts(SecondsFormat::Secs, true),
+        _ => date.format_with_items(items.into_iter()).to_string(),
+    };
+
+    Ok(Value::String(formatted))
+}
+
+#[cfg(test)]
+mod tests {
+    use tera::{Context, Tera};
+
+    use crate::filters::register;
+
+    fn render(template: &str) -> tera::Result<String> {
+        let mut tera = Tera::default();
+        register(&mut tera);
+        tera.render_str(template, &Context::new())
+    }
+
+    #[test]
+    fn slugify() {
+        assert_eq!(
+            "the-5-am-club",
+            render("{{ 'The 5 AM Club!' | slugify }}").unwrap()
+        );
+    }
+
+    #[test]
+    fn wikilink() {
+        assert_eq!("[[A Book]]", render("{{ 'A Book' | wikilink }}").unwrap());
+        assert_eq!(
+            "[[Why- A Story-|Why]]",
+            render("{{ 'Why# A Story|' | wikilink(alias='Why') }}").unwrap()
+        );
+    }
+
+    #[test]
+    fn title_case() {
+        assert_eq!(
+            "The Power of NOW and the Way In",
+            render("{{ 'the power of NOW and the way in' | title_case }}").unwrap()
+        );
+        assert_eq!("", render("{{ '' | title_case }}").unwrap());
+    }
+
+    #[test]
+    fn blockquote() {
+        assert_eq!(
+            "> First line\n>\n> Second line",
+            render("{{ 'First line\n\nSecond line\n' | blockquote }}").unwrap()
+        );
+    }
+
+    #[test]
+    fn truncate_words() {
+        assert_eq!(
+            "One two…",
+            render("{{ 'One two three' | truncate_words(count=2) }}").unwrap()
+        );
+        assert_eq!(
+            "One two...",
+            render("{{ 'One two  three' | truncate_words(count=2, end='...') }}").unwrap()
+        );
+        assert_eq!(
+            "One two three",
+            render("{{ 'One two three' | truncate_words(count=3) }}").unwrap()
+        );
+        assert!(render("{{ 'One' | truncate_words }}").is_err());
+    }
+
+    #[test]
+    fn format_date() {
+        assert_eq!(
+            "21 August 2023",
+            render("{{ '2023-08-21T22:14:52' | format_date(format='%d %B %Y') }}").unwrap()
+        );
+        assert_eq!(
+            "2023-08-22 03:44",
+            render(
+                "{{ '2023-08-21T22:14:52' | format_date(format='%Y-%m-%d %H:%M', timezone='+05:30') }}"
+            )
+            .unwrap()
+        );
+        assert_eq!(
+            "2023-08-21T20:14:52Z",
+            render("{{ '2023-08-21T22:14:52+02:00' | format_date(format='rfc3339') }}").unwrap()
+        );
+        assert_eq!(
+            "2023-08-21",
+            render("{{ '2023-08-21' | format_date }}").unwrap()
+        );
+        assert_eq!("1970-01-02", render("{{ 86400 | format_date }}").unwrap());
+        assert!(render("{{ 'yesterday' | format_date }}").is_err());
+        assert!(render("{{ '2023-08-21' | format_date(timezone='Mars') }}").is_err());
+        assert!(render("{{ '2023-08-21' | format_date(format='%Q') }}").is_err());
+    }
+
+    #[test]
+    fn now() {
+        assert_eq!(
+            chrono::Utc::now().format("%Y").to_string(),
+            render("{{ now(format='%Y') }}").unwrap()
+        );
+        assert_eq!(10, render("{{ now(timezone='local') }}").unwrap().len());
+    }
+}
@@ -0,0 +1,181 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::path::Path;
+
+use serde::Serialize;
+use tera::{Context, Tera};
+
+use crate::model::Book;
+use crate::output::{has_locations, unique_file_name, RenderError};
+
+/// name of the index page written by [render_html]
+pub const INDEX_FILE_NAME: &str = "index.html";
+
+/// directory, within the output directory, that holds a page per book
+pub const BOOKS_DIR_NAME: &str = "books";
+
+#[derive(Serialize)]
+struct BookSummary<'a> {
+    title: &'a str,
+    author: &'a str,
+    href: String,
+    highlight_count: usize,
+    note_count: usize,
+}
+
+#[derive(Serialize)]
+struct SearchEntry<'a> {
+    title: &'a str,
+    author: &'a str,
+    href: &'a str,
+    highlights: Vec<SearchHighlight<'a>>,
+}
+
+#[derive(Serialize)]
+struct SearchHighlight<'a> {
+    quote: &'a str,
+    note: &'a str,
+}
+
+/// Generates a static website for browsing the library in the output [Path]: an `index.html`
+/// listing every book, with client-side search across titles, authors, highlights and notes, and
+/// a page per book under `books/`.
+///
+/// Everything is inlined so the site works offline, straight from `file://`.
+pub fn render_html(library: &HashMap<String, Book>, output_dir: &Path) -> Result<(), RenderError> {
+    let mut tera = Tera::default();
+    tera.add_raw_templates(vec![
+        ("html/base.html", include_str!("templates/html/base.html")),
+        ("html/index.html", include_str!("templates/html/index.html")),
+        ("html/book.html", include_str!("templates/html/book.html")),
+    ])
+    .unwrap();
+
+    let mut books: Vec<&Book> = library.values().collect();
+    books.sort_by(|a, b| a.title().cmp(b.title()));
+
+    let books_dir = output_dir.join(BOOKS_DIR_NAME);
+    if let Err(e) = fs::create_dir_all(&books_dir) {
+        return Err(RenderError::CreateOutputFileFailed(e.to_string()));
+    }
+
+    let mut ctx = Context::new();
+    ctx.insert("version", env!("CARGO_PKG_VERSION"));
+    ctx.insert("date", &chrono::Utc::now().format("%Y-%m-%d").to_string());
+
+    let mut used: HashSet<String> = HashSet::new();
+    let mut summaries: Vec<BookSummary> = Vec::new();
+
+    for book in books.iter() {
+        let file_name = unique_file_name(book.title(), "html", &mut used);
+
+        ctx.insert("book", book);
+        ctx.insert("has_locations", &has_locations(book));
+        render_to_file(&tera, "html/book.html", &ctx, &books_dir.join(&file_name))?;
+
+        summaries.push(BookSummary {
+            title: book.title(),
+            author: book.author(),
+            href: format!("{}/{}", BOOKS_DIR_NAME, file_name),
+            highlight_count: book.highlights().len(),
+            note_count: book
+                .highlights()
+                .values()
+                .filter(|h| h.note().is_some())
+                .count(),
+        });
+    }
+
+    let search_index: Vec<SearchEntry> = books
+        .iter()
+        .zip(summaries.iter())
+        .map(|(book, summary)| SearchEntry {
+            title: book.title(),
+            author: book.author(),
+            href: &summary.href,
+            highlights: book
+                .highlights()
+                .values()
+                .map(|h| SearchHighlight {
+                    quote: h.quote().trim(),
+                    note: h.note().as_ref().map_or("", |n| n.content()),
+                })
+                .collect(),
+        })
+        .collect();
+
+    let search_index = serde_json::to_string(&search_index)
+        .map_err(|e| RenderError::WriteFailed(e.to_string()))?
+        // keep quotes like "</script>" from ending the script early
+        .replace("</", "<\\/");
+
+    ctx.remove("book");
+    ctx.insert("books", &summaries);
+    ctx.insert("search_index", &search_index);
+    render_to_file(
+        &tera,
+        "html/index.html",
+        &ctx,
+        &output_dir.join(INDEX_FILE_NAME),
+    )
+}
+
+fn render_to_file(
+    tera: &Tera,
+    template: &str,
+    ctx: &Context,
+    file_path: &Path,
+) -> Result<(), RenderError> {
+    let file: File = match File::create(file_path) {
+        Ok(f) => f,
+        Err(e) => return Err(RenderError::CreateOutputFileFailed(e.to_string())),
+    };
+
+    tera.render_to(template, ctx, file)
+        .map_err(|e| RenderError::ParsingFailed(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+
+    use crate::html::render_html;
+    use crate::model::{Book, Highlight, HighlightLocation, Note};
+
+    #[test]
+    fn site() {
+        let mut book = Book::new(
+            "The 5 AM Club: Own Your Morning.".to_string(),
+            "Sharma, Robin".to_string(),
+        );
+        book.add_highlight(Highlight::new(
+            3,
+            HighlightLocation::new(40, 42),
+            "<b>Bold</b> claims </script>".to_string(),
+        ));
+        book.add_note(Note::new(3, 41, "A note".to_string()));
+
+        let mut library = HashMap::new();
+        library.insert(book.title().to_string(), book);
+
+        let dir = env::temp_dir().join(format!("ktr-html-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        render_html(&library, &dir).unwrap();
+
+        let index = fs::read_to_string(dir.join("index.html")).unwrap();
+        let page =
+            fs::read_to_string(dir.join("books/the-5-am-club-own-your-morning.html")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(index.contains(r#"<a href="books&#x2F;the-5-am-club-own-your-morning.html">"#));
+        assert!(index.contains(r#"<td class="count">1</td><td class="count">1</td>"#));
+        assert!(index.contains(r#"<\/script>"#));
+        assert!(!index.contains("claims </script>"));
+
+        assert!(page.contains("&lt;b&gt;Bold&lt;&#x2F;b&gt;"));
+        assert!(page.contains("<aside>A note</aside>"));
+        assert!(page.contains(r#"<a href="../index.html">"#));
+    }
+}
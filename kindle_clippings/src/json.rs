@@ -0,0 +1,267 @@
+//! JSON export of a whole library, for other tools to consume.
+//!
+//! The same document doubles as a snapshot of the library, which can be read back in with
+//! [JsonLibrary] to carry an edited or merged library between sessions without re-parsing the
+//! original clippings.
+//!
+//! The document is versioned with `schema_version`, which is bumped whenever a field is removed,
+//! renamed or changes meaning. New fields may be added without a bump, so consumers should ignore
+//! fields they don't know about.
+//!
+//! Version 2 looks like:
+//!
+//! ```json
+//! {
+//!   "schema_version": 2,
+//!   "generator": "ktr 0.1.0",
+//!   "books": [
+//!     {
+//!       "title": "The 5 AM Club: Own Your Morning. Elevate Your Life.",
+//!       "author": "Sharma, Robin",
+//!       "source": "kindle",
+//!       "highlights": [
+//!         {
+//!           "page": 100,
+//!           "location": [1536, 1537],
+//!           "quote": "while growth as a producer and as a person can be hard",
+//!           "note": { "page": 100, "location": 1537, "content": "Smidgen the pigeon" },
+//!           "date": "2023-08-21T22:14:52",
+//!           "chapter": null,
+//!           "tags": []
+//!         }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+//!
+//! Books are ordered by title and highlights by location. `source` is the name of the
+//! [ClippingSource] the book was imported from. `location` is the start and end of the highlight,
+//! followed by a third number when the source put several highlights at exactly the same place,
+//! counting them from 1 after the first. `note`, `date` and `chapter` are `null` when the source
+//! didn't record them.
+//!
+//! Version 2 added `source` and the third number in `location`. Version 1 documents can still be
+//! read, their books are taken to be from a Kindle.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::model::Book;
+use crate::output::RenderError;
+use crate::source::{has_extension, ClippingSource, Import, SourceError};
+
+/// version of the JSON document written by [export_json]
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// name of the file written by [render_json]
+pub const LIBRARY_FILE_NAME: &str = "library.json";
+
+#[derive(Serialize)]
+struct LibraryDocument<'a> {
+    schema_version: u32,
+    generator: String,
+    books: Vec<&'a Book>,
+}
+
+#[derive(Deserialize)]
+struct LibrarySnapshot {
+    schema_version: u32,
+    books: Vec<Book>,
+}
+
+/// A library previously written by [export_json], [render_json] or [save_library].
+pub struct JsonLibrary;
+
+impl ClippingSource for JsonLibrary {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn accepts(&self, path: &Path) -> bool {
+        has_extension(path, &["json"])
+    }
+
+    fn import(&self, path: &Path) -> Result<Import, SourceError> {
+        match File::open(path) {
+            Ok(f) => Ok(Import {
+                library: import_json(BufReader::new(f))?,
+                diagnostics: Vec::new(),
+            }),
+            Err(e) => Err(SourceError::ReadFailed(e.to_string())),
+        }
+    }
+}
+
+/// write the library to `writer` as a JSON document, see the [module documentation](self) for
+/// its schema
+pub fn export_json<W>(library: &HashMap<String, Book>, writer: W) -> Result<(), RenderError>
+where
+    W: Write,
+{
+    let mut books: Vec<&Book> = library.values().collect();
+    books.sort_by(|a, b| a.title().cmp(b.title()));
+
+    let document = LibraryDocument {
+        schema_version: SCHEMA_VERSION,
+        generator: format!("ktr {}", env!("CARGO_PKG_VERSION")),
+        books,
+    };
+
+    serde_json::to_writer_pretty(writer, &document)
+        .map_err(|e| RenderError::WriteFailed(e.to_string()))
+}
+
+/// write the library to a `library.json` file in the output [Path]
+pub fn render_json(library: &HashMap<String, Book>, output_dir: &Path) -> Result<(), RenderError> {
+    let mut file_path = output_dir.to_path_buf();
+    file_path.push(LIBRARY_FILE_NAME);
+
+    save_library(library, &file_path)
+}
+
+/// write a snapshot of the library to the given file
+pub fn save_library(library: &HashMap<String, Book>, file_path: &Path) -> Result<(), RenderError> {
+    let file = match File::create(file_path) {
+        Ok(f) => f,
+        Err(e) => return Err(RenderError::CreateOutputFileFailed(e.to_string())),
+    };
+
+    let mut writer = BufWriter::new(file);
+    export_json(library, &mut writer)?;
+    writer
+        .flush()
+        .map_err(|e| RenderError::WriteFailed(e.to_string()))
+}
+
+/// read a library written by [export_json] back in
+pub fn import_json<R>(reader: R) -> Result<HashMap<String, Book>, SourceError>
+where
+    R: Read,
+{
+    let snapshot: LibrarySnapshot =
+        serde_json::from_reader(reader).map_err(|e| SourceError::ParsingFailed(e.to_string()))?;
+
+    if snapshot.schema_version > SCHEMA_VERSION {
+        return Err(SourceError::ParsingFailed(format!(
+            "library was written with schema version {}, only {} or older can be read",
+            snapshot.schema_version, SCHEMA_VERSION
+        )));
+    }
+
+    Ok(snapshot
+        .books
+        .into_iter()
+        .map(|b| (b.title().to_string(), b))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::json::{export_json, import_json, SCHEMA_VERSION};
+    use crate::model::{Book, Highlight, HighlightLocation, Note};
+
+    #[test]
+    fn schema() {
+        let mut book = Book::new("A Book".to_string(), "Doe, Jane".to_string());
+        book.add_highlight(Highlight::new(
+            3,
+            HighlightLocation::new(40, 42),
+            "A quote".to_string(),
+        ));
+        book.add_note(Note::new(3, 41, "A note".to_string()));
+
+        let mut library = HashMap::new();
+        library.insert(book.title().to_string(), book);
+
+        let mut out: Vec<u8> = Vec::new();
+        export_json(&library, &mut out).unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&out).unwrap();
+
+        assert_eq!(SCHEMA_VERSION as u64, json["schema_version"]);
+        let hl = &json["books"][0]["highlights"][0];
+        assert_eq!("Doe, Jane", json["books"][0]["author"]);
+        assert_eq!("kindle", json["books"][0]["source"]);
+        assert_eq!(serde_json::json!([40, 42]), hl["location"]);
+        assert_eq!("A quote", hl["quote"]);
+        assert_eq!("A note", hl["note"]["content"]);
+        assert!(hl["date"].is_null());
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut book = Book::new("A Book".to_string(), "Doe, Jane".to_string());
+        let mut highlight =
+            Highlight::new(3, HighlightLocation::new(40, 42), "A quote".to_string());
+        highlight.add_tag("ideas".to_string());
+        book.add_highlight(highlight);
+        book.add_note(Note::new(3, 41, "A note".to_string()));
+        book.add_highlight(Highlight::new(
+            3,
+            HighlightLocation::with_ordinal(40, 42, 1),
+            "Another quote".to_string(),
+        ));
+        book.set_source("kobo");
+
+        let mut library = HashMap::new();
+        library.insert(book.title().to_string(), book);
+
+        let mut out: Vec<u8> = Vec::new();
+        export_json(&library, &mut out).unwrap();
+        let loaded = import_json(out.as_slice()).unwrap();
+
+        let book = loaded.get("A Book").unwrap();
+        assert_eq!("Doe, Jane", book.author());
+        assert_eq!("kobo", book.source());
+        assert_eq!(
+            "Another quote",
+            book.highlights()
+                .get(&HighlightLocation::with_ordinal(40, 42, 1))
+                .unwrap()
+                .quote()
+        );
+
+        let hl = book
+            .highlights()
+            .get(&HighlightLocation::new(40, 42))
+            .unwrap();
+        assert_eq!("A quote", hl.quote());
+        assert_eq!(&["ideas".to_string()], hl.tags());
+        assert_eq!("A note", hl.note().clone().unwrap().content());
+    }
+
+    #[test]
+    fn version_1() {
+        let json = r#"{
+  "schema_version": 1,
+  "books": [
+    {
+      "title": "A Book",
+      "author": "Doe, Jane",
+      "highlights": [
+        { "page": 3, "location": [40, 42], "quote": "A quote", "note": null }
+      ]
+    }
+  ]
+}"#;
+        let library = import_json(json.as_bytes()).unwrap();
+        let book = library.get("A Book").unwrap();
+
+        assert_eq!("kindle", book.source());
+        assert!(book
+            .highlights()
+            .contains_key(&HighlightLocation::new(40, 42)));
+    }
+
+    #[test]
+    fn newer_schema() {
+        let json = r#"{ "schema_version": 999, "books": [] }"#;
+        assert!(import_json(json.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,320 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+use rusqlite::{Connection, OpenFlags};
+
+use crate::model::{Book, Highlight, HighlightLocation, Note};
+use crate::source::{has_extension, ClippingSource, Import, SourceError};
+
+/// The `KoboReader.sqlite` database found under the `.kobo` directory of a Kobo.
+pub struct Kobo;
+
+impl ClippingSource for Kobo {
+    fn name(&self) -> &'static str {
+        "kobo"
+    }
+
+    fn accepts(&self, path: &Path) -> bool {
+        has_extension(path, &["sqlite", "sqlite3", "db"])
+    }
+
+    fn has_locations(&self) -> bool {
+        false
+    }
+
+    fn import(&self, path: &Path) -> Result<Import, SourceError> {
+        if !path.is_file() {
+            return Err(SourceError::ReadFailed(format!(
+                "{} is not a file",
+                path.display()
+            )));
+        }
+
+        match parse_kobo_database(path) {
+            Ok(library) => Ok(Import {
+                library,
+                diagnostics: Vec::new(),
+            }),
+            Err(e) => Err(SourceError::ParsingFailed(e.to_string())),
+        }
+    }
+}
+
+/// Kobo doesn't record page numbers, so bookmarks are ordered by chapter and progress through the
+/// chapter. The chapter's position in the book is used as the page, see [kobo_location] for the
+/// location.
+const BOOKMARKS_QUERY: &str = "
+SELECT
+    book.Title,
+    COALESCE(book.Attribution, ''),
+    (SELECT c.Title FROM content c
+        WHERE c.ContentID LIKE b.ContentID || '%' AND c.ContentType != 6
+        ORDER BY c.VolumeIndex LIMIT 1),
+    (SELECT c.VolumeIndex FROM content c
+        WHERE c.ContentID LIKE b.ContentID || '%' AND c.ContentType != 6
+        ORDER BY c.VolumeIndex LIMIT 1) AS chapter_index,
+    b.Text,
+    b.Annotation,
+    b.DateCreated,
+    b.ChapterProgress
+FROM Bookmark b
+INNER JOIN content book ON book.ContentID = b.VolumeID AND book.ContentType = 6
+WHERE b.Text IS NOT NULL AND TRIM(b.Text) != ''
+ORDER BY book.Title, chapter_index, b.ChapterProgress, b.DateCreated";
+
+/// open a Kobo `KoboReader.sqlite` database (found under `.kobo` on the device) and return a
+/// [HashMap<String, Book>] where the [String] represents the book's title
+pub fn parse_kobo_database<P>(database: P) -> rusqlite::Result<HashMap<String, Book>>
+where
+    P: AsRef<Path>,
+{
+    let conn = Connection::open_with_flags(database, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut stmt = conn.prepare(BOOKMARKS_QUERY)?;
+
+    let rows = stmt.query_map([], |row| {
+        Ok(Bookmark {
+            title: row.get(0)?,
+            author: row.get(1)?,
+            chapter: row.get(2)?,
+            chapter_index: row.get(3)?,
+            text: row.get(4)?,
+            annotation: row.get(5)?,
+            date_created: row.get(6)?,
+            chapter_progress: row.get(7)?,
+        })
+    })?;
+
+    let mut library: HashMap<String, Book> = HashMap::new();
+
+    for bookmark in rows {
+        let bookmark = bookmark?;
+
+        let book = library.entry(bookmark.title.clone()).or_insert_with(|| {
+            let mut book = Book::new(bookmark.title.clone(), bookmark.author.clone());
+            book.set_source("kobo");
+            book
+        });
+
+        let page = bookmark.chapter_index.map_or(0, |i| i.max(0) as u64 + 1);
+        // highlights at the same point are told apart by the order they were made in
+        let location = kobo_location(page, bookmark.chapter_progress);
+        let mut location_key = HighlightLocation::new(location, location);
+        while book.highlights().contains_key(&location_key) {
+            location_key =
+                HighlightLocation::with_ordinal(location, location, location_key.ordinal() + 1);
+        }
+
+        let mut highlight = Highlight::new(page, location_key, clean_text(&bookmark.text));
+
+        if let Some(chapter) = bookmark.chapter.filter(|c| !c.trim().is_empty()) {
+            highlight.add_chapter(chapter.trim().to_string());
+        }
+
+        if let Some(date) = bookmark.date_created.as_deref().and_then(parse_date) {
+            highlight.add_date(date);
+        }
+
+        // the annotation belongs to this bookmark, not whichever highlights are near it
+        if let Some(annotation) = bookmark.annotation.filter(|a| !a.trim().is_empty()) {
+            highlight.add_note(Note::new(page, location, clean_text(&annotation)));
+        }
+
+        book.add_highlight(highlight);
+    }
+
+    Ok(library)
+}
+
+struct Bookmark {
+    title: String,
+    author: String,
+    chapter: Option<String>,
+    chapter_index: Option<i64>,
+    text: String,
+    annotation: Option<String>,
+    date_created: Option<String>,
+    chapter_progress: Option<f64>,
+}
+
+/// how far through a chapter a location is measured in, [kobo_location] keeps locations within a
+/// chapter below this
+const CHAPTER_LOCATIONS: u64 = 100_000;
+
+/// a location that stays the same however many highlights are added to the book: the chapter's
+/// `page` scaled up, plus how far through the chapter the highlight is
+fn kobo_location(page: u64, chapter_progress: Option<f64>) -> u64 {
+    let progress = chapter_progress.unwrap_or(0.0).clamp(0.0, 1.0);
+    page * CHAPTER_LOCATIONS + (progress * (CHAPTER_LOCATIONS - 1) as f64).round() as u64
+}
+
+/// Kobo keeps the line breaks of the highlighted passage, the Kindle parser flattens them
+fn clean_text(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+/// Kobo stores dates as ISO 8601, with or without fractional seconds and a trailing `Z`
+fn parse_date(date: &str) -> Option<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(date.trim_end_matches('Z'), "%Y-%m-%dT%H:%M:%S%.f").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use rusqlite::Connection;
+
+    use crate::kobo::parse_kobo_database;
+    use crate::model::HighlightLocation;
+    use crate::output::{render_book, Format};
+
+    fn create_database(name: &str) -> std::path::PathBuf {
+        let path = env::temp_dir().join(format!("ktr-{}-{}.sqlite", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "
+CREATE TABLE content (
+    ContentID TEXT PRIMARY KEY, ContentType TEXT, BookID TEXT, Title TEXT,
+    Attribution TEXT, VolumeIndex INTEGER
+);
+CREATE TABLE Bookmark (
+    BookmarkID TEXT PRIMARY KEY, VolumeID TEXT, ContentID TEXT, Text TEXT,
+    Annotation TEXT, DateCreated TEXT, ChapterProgress REAL
+);
+INSERT INTO content VALUES
+    ('file:///mnt/onboard/club.kepub.epub', 6, NULL, 'The 5 AM Club', 'Robin Sharma', -1),
+    ('file:///mnt/onboard/club.kepub.epub!OEBPS!ch01.xhtml-1', 9,
+        'file:///mnt/onboard/club.kepub.epub', 'Chapter One', NULL, 0),
+    ('file:///mnt/onboard/club.kepub.epub!OEBPS!ch02.xhtml-1', 9,
+        'file:///mnt/onboard/club.kepub.epub', 'Chapter Two', NULL, 1);
+INSERT INTO Bookmark VALUES
+    ('b1', 'file:///mnt/onboard/club.kepub.epub',
+        'file:///mnt/onboard/club.kepub.epub!OEBPS!ch02.xhtml',
+        'Gamble everything for love,
+if you are a true human being.', 'Smidgen the pigeon', '2023-08-21T22:01:40.000', 0.5),
+    ('b2', 'file:///mnt/onboard/club.kepub.epub',
+        'file:///mnt/onboard/club.kepub.epub!OEBPS!ch01.xhtml',
+        'The Top 5% go granular.', NULL, '2023-08-20T21:13:59Z', 0.2),
+    ('b3', 'file:///mnt/onboard/club.kepub.epub',
+        'file:///mnt/onboard/club.kepub.epub!OEBPS!ch01.xhtml',
+        NULL, NULL, '2023-08-20T21:20:00Z', 0.3);
+",
+        )
+        .unwrap();
+
+        path
+    }
+
+    #[test]
+    fn model() {
+        let path = create_database("kobo");
+        let library = parse_kobo_database(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(1, library.len());
+
+        let book = library.get("The 5 AM Club").unwrap();
+        assert_eq!("Robin Sharma", book.author());
+        assert_eq!("kobo", book.source());
+        assert_eq!(2, book.highlights().len());
+
+        // locations made up from chapter progress aren't shown
+        let org = render_book(book, &None, Format::Org).unwrap();
+        assert!(org.contains("\n* Page 2\n") && !org.contains("Location"));
+
+        let first = book
+            .highlights()
+            .get(&HighlightLocation::new(120_000, 120_000))
+            .unwrap();
+        assert_eq!("The Top 5% go granular.", first.quote());
+        assert_eq!(&Some("Chapter One".to_string()), first.chapter());
+        assert_eq!(&1, first.page());
+        assert_eq!(
+            "2023-08-20 21:13:59",
+            first
+                .date()
+                .unwrap()
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        );
+
+        let second = book
+            .highlights()
+            .get(&HighlightLocation::new(250_000, 250_000))
+            .unwrap();
+        assert_eq!(
+            "Gamble everything for love, if you are a true human being.",
+            second.quote()
+        );
+        assert_eq!(&Some("Chapter Two".to_string()), second.chapter());
+        assert_eq!(
+            "Smidgen the pigeon",
+            second.note().clone().unwrap().content()
+        );
+    }
+
+    #[test]
+    fn stable_locations() {
+        let path = create_database("kobo-stable");
+        let conn = Connection::open(&path).unwrap();
+        conn.execute_batch(
+            "
+INSERT INTO Bookmark VALUES
+    ('b4', 'file:///mnt/onboard/club.kepub.epub',
+        'file:///mnt/onboard/club.kepub.epub!OEBPS!ch01.xhtml',
+        'An earlier highlight.', NULL, '2023-08-22T08:00:00Z', 0.1),
+    ('b5', 'file:///mnt/onboard/club.kepub.epub',
+        'file:///mnt/onboard/club.kepub.epub!OEBPS!ch02.xhtml',
+        'Right next to the pigeon.', 'Another note', '2023-08-22T09:00:00Z', 0.5),
+    ('b6', 'file:///mnt/onboard/club.kepub.epub',
+        'file:///mnt/onboard/club.kepub.epub!OEBPS!ch02.xhtml',
+        'A little further on.', NULL, '2023-08-22T10:00:00Z', 0.50002);
+",
+        )
+        .unwrap();
+        drop(conn);
+        let library = parse_kobo_database(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let book = library.get("The 5 AM Club").unwrap();
+        let quote = |location: HighlightLocation| {
+            let hl = book.highlights().get(&location).unwrap();
+            (
+                hl.quote(),
+                hl.note().as_ref().map(|n| n.content().to_string()),
+            )
+        };
+
+        // highlights made earlier in the book don't move the ones already there
+        assert_eq!(5, book.highlights().len());
+        assert_eq!(
+            ("An earlier highlight.", None),
+            quote(HighlightLocation::new(110_000, 110_000))
+        );
+        assert_eq!(
+            ("The Top 5% go granular.", None),
+            quote(HighlightLocation::new(120_000, 120_000))
+        );
+        assert_eq!(
+            (
+                "Gamble everything for love, if you are a true human being.",
+                Some("Smidgen the pigeon".to_string())
+            ),
+            quote(HighlightLocation::new(250_000, 250_000))
+        );
+        // a second highlight at the same point keeps its own location and note
+        assert_eq!(
+            (
+                "Right next to the pigeon.",
+                Some("Another note".to_string())
+            ),
+            quote(HighlightLocation::with_ordinal(250_000, 250_000, 1))
+        );
+        assert_eq!(
+            ("A little further on.", None),
+            quote(HighlightLocation::new(250_001, 250_001))
+        );
+    }
+}
@@ -1,3 +1,4 @@
+use chrono::NaiveDateTime;
 use regex::Regex;
 use std::collections::HashMap;
 use std::fs::{self};
@@ -5,9 +6,47 @@ use std::io::{self};
 use std::path::Path;
 
 use self::model::{Book, Highlight, HighlightLocation, Note};
-
+use self::source::{has_extension, ClippingSource, Diagnostic, Import, SourceError};
+
+pub mod anki;
+pub mod atomic;
+pub mod epub;
+pub mod filename;
+mod filters;
+pub mod html;
+pub mod json;
+pub mod kobo;
+pub mod manifest;
 pub mod model;
 pub mod output;
+pub mod pdf;
+pub mod preview;
+pub mod readwise;
+pub mod renderer;
+pub mod rules;
+pub mod source;
+pub mod sync;
+pub mod validate;
+
+/// The `My Clippings.txt` file found under the `documents` directory of a Kindle.
+pub struct Kindle;
+
+impl ClippingSource for Kindle {
+    fn name(&self) -> &'static str {
+        "kindle"
+    }
+
+    fn accepts(&self, path: &Path) -> bool {
+        has_extension(path, &["txt"])
+    }
+
+    fn import(&self, path: &Path) -> Result<Import, SourceError> {
+        match read_file_string(path) {
+            Ok(s) => Ok(parse_entries(s)),
+            Err(e) => Err(SourceError::ReadFailed(e.to_string())),
+        }
+    }
+}
 
 /// read a clippings file and return it as a [Vec<String>]
 pub fn read_file_string<P>(filename: P) -> io::Result<Vec<String>>
@@ -26,16 +65,21 @@ where
 /// using a [Vec<String>] as the input, return a [HashMap<String, Book>] where the [String]
 /// represents the book's title
 pub fn parse_clippings(clippings: Vec<String>) -> HashMap<String, Book> {
+    parse_entries(clippings).library
+}
+
+fn parse_entries(clippings: Vec<String>) -> Import {
     let mut library: HashMap<String, Book> = HashMap::new();
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
 
     let re_highlights = Regex::new(r"\s*(?<title>.*)\s\((?<author>.*,.*)\) - Your Highlight on page (?<page>\d+) \| location (?<loc_start>\d+)-(?<loc_end>\d+) \| Added on (?<timestamp>.+ \d{4} \d{2}:\d{2}:\d{2})\s+(?<quote>.*)\s*").unwrap();
 
     let re_note = Regex::new(r"\s*(?<title>.*)\s\((?<author>.*,.*)\) - Your Note on page (?<page>\d+) \| location (?<location>\d+) \| Added on (?<timestamp>.+ \d{4} \d{2}:\d{2}:\d{2})\s+(?<note>.*)\s*").unwrap();
 
-    for entry in clippings.iter() {
+    for (i, entry) in clippings.iter().enumerate() {
         // if we have a highlight
         // check for highlights first as they'll likely be more common
-        if let Some((_, [title, author, page, loc_start, loc_end, _timestamp, quote])) =
+        if let Some((_, [title, author, page, loc_start, loc_end, timestamp, quote])) =
             re_highlights.captures(entry).map(|c| c.extract())
         {
             // ensure that we have the book in our library
@@ -46,34 +90,56 @@ pub fn parse_clippings(clippings: Vec<String>) -> HashMap<String, Book> {
                 );
             }
 
-            library
-                .get_mut(title)
-                .unwrap()
-                .add_highlight(Highlight::new(
-                    page.parse().unwrap(),
-                    HighlightLocation::new(loc_start.parse().unwrap(), loc_end.parse().unwrap()),
-                    quote.to_string(),
-                ));
+            let mut highlight = Highlight::new(
+                page.parse().unwrap(),
+                HighlightLocation::new(loc_start.parse().unwrap(), loc_end.parse().unwrap()),
+                quote.to_string(),
+            );
+
+            if let Ok(date) = NaiveDateTime::parse_from_str(timestamp, "%A, %d %B %Y %H:%M:%S") {
+                highlight.add_date(date);
+            }
+
+            library.get_mut(title).unwrap().add_highlight(highlight);
 
         // If we don't have a highlight, check for a note
         } else if let Some((_, [title, _author, page, location, _timestamp, note])) =
             re_note.captures(entry).map(|c| c.extract())
         {
-            library.get_mut(title).unwrap().add_note(Note::new(
-                page.parse().unwrap(),
-                location.parse().unwrap(),
-                note.trim().to_string(),
+            match library.get_mut(title) {
+                Some(book) => book.add_note(Note::new(
+                    page.parse().unwrap(),
+                    location.parse().unwrap(),
+                    note.trim().to_string(),
+                )),
+                None => diagnostics.push(Diagnostic::new(
+                    Some(i + 1),
+                    format!("note for '{}' has no highlight to attach to", title),
+                )),
+            }
+
+        // bookmarks and clippings in other formats end up here
+        } else if !entry.trim().is_empty() {
+            diagnostics.push(Diagnostic::new(
+                Some(i + 1),
+                format!(
+                    "not a recognised highlight or note: {}",
+                    entry.trim().chars().take(60).collect::<String>()
+                ),
             ));
         }
     }
 
-    library
+    Import {
+        library,
+        diagnostics,
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use crate::model::HighlightLocation;
-    use crate::parse_clippings;
+    use crate::{parse_clippings, parse_entries};
 
     fn get_input() -> Vec<String> {
         let input: Vec<String> = "\
@@ -134,8 +200,26 @@ Smidgen the pigeon
         let hl = book.highlights().get(&loc).unwrap();
 
         assert_eq!(&100, hl.page());
+        assert_eq!(
+            "2023-08-21 22:14:52",
+            hl.date().unwrap().format("%Y-%m-%d %H:%M:%S").to_string()
+        );
 
         let note = hl.note().clone().unwrap();
         assert_eq!("Smidgen the pigeon", note.content());
     }
+
+    #[test]
+    fn diagnostics() {
+        let mut input = get_input();
+        input.insert(
+            0,
+            " A Book (Doe, Jane) - Your Bookmark on page 3 | location 41 | Added on Sunday, 20 August 2023 21:13:59  ".to_string(),
+        );
+
+        let import = parse_entries(input);
+        assert_eq!(1, import.library.len());
+        assert_eq!(1, import.diagnostics.len());
+        assert_eq!(Some(1), import.diagnostics[0].entry);
+    }
 }
@@ -0,0 +1,334 @@
+//! A record of what's been exported to an output directory, so later runs can export only the
+//! highlights that are new or have changed since.
+//!
+//! The manifest is kept in `.ktr-manifest.json` in the output directory. It maps the id of each
+//! exported highlight, see [highlight_id], to a hash of its quote and note.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufReader, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{NaiveDateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+use tera::{Context, Tera};
+
+use crate::model::{Book, Highlight, HighlightLocation};
+use crate::output::{highlight_id, now_date, write_atomically, HighlightView, RenderError};
+
+/// name of the manifest file kept in the output directory
+pub const MANIFEST_FILE_NAME: &str = ".ktr-manifest.json";
+
+/// version of the manifest written by [Manifest::save]
+pub const MANIFEST_VERSION: u32 = 1;
+
+/// What was exported to an output directory, and when.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    schema_version: u32,
+    synced_at: Option<NaiveDateTime>,
+    highlights: BTreeMap<String, String>,
+}
+
+/// The highlights in a library that a [Manifest] hasn't seen, or has seen with different content.
+#[derive(Debug, Default)]
+pub struct Changes {
+    /// the library cut down to the new and changed highlights, books without any are left out
+    pub library: HashMap<String, Book>,
+    /// the changes to each book, ordered by title
+    pub books: Vec<BookChanges>,
+}
+
+/// The new and changed highlights in a single book.
+#[derive(Debug)]
+pub struct BookChanges {
+    pub title: String,
+    pub new: Vec<HighlightLocation>,
+    pub changed: Vec<HighlightLocation>,
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.books.is_empty()
+    }
+
+    pub fn new_count(&self) -> usize {
+        self.books.iter().map(|b| b.new.len()).sum()
+    }
+
+    pub fn changed_count(&self) -> usize {
+        self.books.iter().map(|b| b.changed.len()).sum()
+    }
+}
+
+impl std::fmt::Display for BookChanges {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: {} new, {} changed",
+            self.title,
+            self.new.len(),
+            self.changed.len()
+        )
+    }
+}
+
+impl Default for Manifest {
+    fn default() -> Self {
+        Manifest {
+            schema_version: MANIFEST_VERSION,
+            synced_at: None,
+            highlights: BTreeMap::new(),
+        }
+    }
+}
+
+impl Manifest {
+    /// read the manifest from the output [Path], or start an empty one if there isn't one yet
+    pub fn load(output_dir: &Path) -> Result<Manifest, RenderError> {
+        let file = match File::open(output_dir.join(MANIFEST_FILE_NAME)) {
+            Ok(f) => f,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Manifest::default()),
+            Err(e) => return Err(RenderError::ReadExistingFailed(e.to_string())),
+        };
+
+        let manifest: Manifest = serde_json::from_reader(BufReader::new(file))
+            .map_err(|e| RenderError::ReadExistingFailed(e.to_string()))?;
+
+        if manifest.schema_version > MANIFEST_VERSION {
+            return Err(RenderError::ReadExistingFailed(format!(
+                "manifest was written with version {}, only {} or older can be read",
+                manifest.schema_version, MANIFEST_VERSION
+            )));
+        }
+
+        Ok(manifest)
+    }
+
+    /// write the manifest to the output [Path]
+    ///
+    /// The previous manifest is only replaced once the new one is written in full, so a run that's
+    /// cut short doesn't lose track of what was exported.
+    pub fn save(&self, output_dir: &Path) -> Result<(), RenderError> {
+        write_atomically(&output_dir.join(MANIFEST_FILE_NAME), false, |writer| {
+            serde_json::to_writer_pretty(writer, self)
+                .map_err(|e| RenderError::WriteFailed(e.to_string()))
+        })
+    }
+
+    /// when the manifest was last recorded, [None] if it never has been
+    pub fn synced_at(&self) -> Option<NaiveDateTime> {
+        self.synced_at
+    }
+
+    /// the highlights in the library that are new or have changed since the manifest was recorded
+    pub fn changes(&self, library: &HashMap<String, Book>) -> Changes {
+        let mut books: Vec<&Book> = library.values().collect();
+        books.sort_by(|a, b| a.title().cmp(b.title()));
+
+        let mut changes = Changes::default();
+
+        for book in books {
+            let mut changed_book = Book::new(book.title().to_string(), book.author().to_string());
+            changed_book.set_source(book.source());
+            let mut book_changes = BookChanges {
+                title: book.title().to_string(),
+                new: Vec::new(),
+                changed: Vec::new(),
+            };
+
+            for (location, highlight) in book.highlights().iter() {
+                let id = highlight_id(book, location).to_string();
+                match self.highlights.get(&id) {
+                    Some(hash) if *hash == content_hash(highlight) => continue,
+                    Some(_) => book_changes.changed.push(location.clone()),
+                    None => book_changes.new.push(location.clone()),
+                }
+                changed_book.add_highlight(highlight.clone());
+            }
+
+            if !changed_book.highlights().is_empty() {
+                changes
+                    .library
+                    .insert(book.title().to_string(), changed_book);
+                changes.books.push(book_changes);
+            }
+        }
+
+        changes
+    }
+
+    /// remember every highlight in the library as exported, as of now
+    pub fn record(&mut self, library: &HashMap<String, Book>) {
+        for book in library.values() {
+            for (location, highlight) in book.highlights().iter() {
+                self.highlights.insert(
+                    highlight_id(book, location).to_string(),
+                    content_hash(highlight),
+                );
+            }
+        }
+        self.synced_at = Some(Utc::now().naive_utc());
+    }
+}
+
+/// a hash of the parts of a highlight a reader can change, its quote and note
+fn content_hash(highlight: &Highlight) -> String {
+    let digest = Sha1::digest(
+        format!(
+            "{}\x1f{}",
+            highlight.quote().trim(),
+            highlight.note().as_ref().map_or("", |n| n.content().trim())
+        )
+        .as_bytes(),
+    );
+
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[derive(Serialize)]
+struct DigestBook<'a> {
+    title: &'a str,
+    author: &'a str,
+    new: Vec<HighlightView<'a>>,
+    changed: Vec<HighlightView<'a>>,
+}
+
+fn views<'a>(book: &'a Book, locations: &[HighlightLocation]) -> Vec<HighlightView<'a>> {
+    locations
+        .iter()
+        .filter_map(|l| {
+            book.highlights()
+                .get(l)
+                .map(|h| HighlightView::new(book, l, h))
+        })
+        .collect()
+}
+
+/// Writes a "new since last sync" note to the output [Path], listing the new and changed
+/// highlights by book, and returns where it was written.
+pub fn render_digest(changes: &Changes, output_dir: &Path) -> Result<PathBuf, RenderError> {
+    let mut tera = Tera::default();
+    tera.add_raw_template("digest", include_str!("templates/digest.md"))
+        .unwrap();
+
+    let books: Vec<DigestBook> = changes
+        .books
+        .iter()
+        .filter_map(|c| changes.library.get(&c.title).map(|b| (c, b)))
+        .map(|(c, book)| DigestBook {
+            title: book.title(),
+            author: book.author(),
+            new: views(book, &c.new),
+            changed: views(book, &c.changed),
+        })
+        .collect();
+
+    let mut ctx = Context::new();
+    ctx.insert("date", &now_date());
+    ctx.insert("books", &books);
+
+    // named to the second, and numbered if need be, so no run overwrites another's digest
+    let name = format!(
+        "New since last sync {}",
+        Utc::now().format("%Y-%m-%d %H%M%S")
+    );
+    let mut file_path = output_dir.join(format!("{}.md", name));
+    let mut i = 2;
+    while file_path.exists() {
+        file_path = output_dir.join(format!("{} {}.md", name, i));
+        i += 1;
+    }
+
+    let content = tera
+        .render("digest", &ctx)
+        .map_err(|e| RenderError::ParsingFailed(e.to_string()))?;
+    write_atomically(&file_path, false, |writer| {
+        writer
+            .write_all(content.as_bytes())
+            .map_err(|e| RenderError::WriteFailed(e.to_string()))
+    })?;
+
+    Ok(file_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+
+    use crate::manifest::{render_digest, Manifest};
+    use crate::model::{Book, Highlight, HighlightLocation, Note};
+
+    fn library(quotes: &[(u64, &str)]) -> HashMap<String, Book> {
+        let mut book = Book::new("A Book".to_string(), "Doe, Jane".to_string());
+        for (location, quote) in quotes {
+            book.add_highlight(Highlight::new(
+                1,
+                HighlightLocation::new(*location, *location),
+                quote.to_string(),
+            ));
+        }
+
+        let mut library = HashMap::new();
+        library.insert(book.title().to_string(), book);
+        library
+    }
+
+    #[test]
+    fn new_and_changed() {
+        let dir = env::temp_dir().join(format!("ktr-manifest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut manifest = Manifest::load(&dir).unwrap();
+        let first = library(&[(10, "First"), (20, "Second")]);
+        assert_eq!(2, manifest.changes(&first).new_count());
+        manifest.record(&first);
+        manifest.save(&dir).unwrap();
+        // only the manifest, no temporary file is left behind
+        assert_eq!(1, fs::read_dir(&dir).unwrap().count());
+
+        let manifest = Manifest::load(&dir).unwrap();
+        assert!(manifest.synced_at().is_some());
+        assert!(manifest.changes(&first).is_empty());
+
+        let mut second = library(&[(10, "First"), (20, "Second"), (30, "Third")]);
+        second
+            .get_mut("A Book")
+            .unwrap()
+            .add_note(Note::new(1, 20, "Now with a note".to_string()));
+        second.get_mut("A Book").unwrap().set_source("kobo");
+        let changes = manifest.changes(&second);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(1, changes.new_count());
+        assert_eq!(1, changes.changed_count());
+        assert_eq!("A Book: 1 new, 1 changed", changes.books[0].to_string());
+
+        let book = changes.library.get("A Book").unwrap();
+        assert_eq!(2, book.highlights().len());
+        assert_eq!("kobo", book.source());
+        assert!(!book
+            .highlights()
+            .contains_key(&HighlightLocation::new(10, 10)));
+    }
+
+    #[test]
+    fn digest() {
+        let changes = Manifest::default().changes(&library(&[(10, "First")]));
+
+        let dir = env::temp_dir().join(format!("ktr-digest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file_path = render_digest(&changes, &dir).unwrap();
+        let again = render_digest(&changes, &dir).unwrap();
+        let digest = fs::read_to_string(&file_path).unwrap();
+        let digests = fs::read_dir(&dir).unwrap().count();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(digest.contains("## A Book\n\nDoe, Jane\n\n> First\n"));
+        assert_ne!(file_path, again);
+        assert_eq!(2, digests);
+    }
+}
@@ -1,8 +1,9 @@
 use std::collections::BTreeMap;
 
-use serde::Serialize;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Note {
     page: u64,
     location: u64,
@@ -31,12 +32,39 @@ impl Note {
     }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize)]
-pub struct HighlightLocation(u64, u64);
+/// Where a highlight starts and ends, and its position among any others the source put at
+/// exactly the same place, counting from 0.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct HighlightLocation(
+    u64,
+    u64,
+    #[serde(default, skip_serializing_if = "is_first")] u32,
+);
+
+fn is_first(ordinal: &u32) -> bool {
+    *ordinal == 0
+}
 
 impl HighlightLocation {
     pub fn new(start: u64, end: u64) -> Self {
-        HighlightLocation(start, end)
+        HighlightLocation(start, end, 0)
+    }
+
+    /// a location shared with other highlights, `ordinal` tells them apart
+    pub fn with_ordinal(start: u64, end: u64, ordinal: u32) -> Self {
+        HighlightLocation(start, end, ordinal)
+    }
+
+    pub fn start(&self) -> u64 {
+        self.0
+    }
+
+    pub fn end(&self) -> u64 {
+        self.1
+    }
+
+    pub fn ordinal(&self) -> u32 {
+        self.2
     }
 
     pub fn contains_location(&self, location: u64) -> bool {
@@ -46,12 +74,18 @@ impl HighlightLocation {
     }
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Highlight {
     page: u64,
     location: HighlightLocation,
     quote: String,
     note: Option<Note>,
+    #[serde(default)]
+    date: Option<NaiveDateTime>,
+    #[serde(default)]
+    chapter: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
 }
 
 impl Highlight {
@@ -61,6 +95,9 @@ impl Highlight {
             location,
             quote,
             note: None,
+            date: None,
+            chapter: None,
+            tags: Vec::new(),
         }
     }
 
@@ -72,31 +109,108 @@ impl Highlight {
         &self.page
     }
 
+    pub fn quote(&self) -> &str {
+        &self.quote
+    }
+
     pub fn note(&self) -> &Option<Note> {
         &self.note
     }
 
+    /// when the highlight was made, if the source recorded it
+    pub fn date(&self) -> &Option<NaiveDateTime> {
+        &self.date
+    }
+
+    /// the chapter the highlight was made in, if the source recorded it
+    pub fn chapter(&self) -> &Option<String> {
+        &self.chapter
+    }
+
+    /// tags given to the highlight, if the source supports them
+    pub fn tags(&self) -> &[String] {
+        &self.tags
+    }
+
     pub fn add_quote(&mut self, quote: String) {
         self.quote = quote;
     }
 
-    fn add_note(&mut self, note: Note) {
+    pub fn add_date(&mut self, date: NaiveDateTime) {
+        self.date = Some(date);
+    }
+
+    pub fn add_chapter(&mut self, chapter: String) {
+        self.chapter = Some(chapter);
+    }
+
+    pub fn add_tag(&mut self, tag: String) {
+        if !self.tags.contains(&tag) {
+            self.tags.push(tag);
+        }
+    }
+
+    /// give the highlight a note directly, for sources that record the two together, see
+    /// [Book::add_note] for notes that have to be matched up by location
+    pub fn add_note(&mut self, note: Note) {
         self.note = Some(note);
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Book {
     title: String,
     author: String,
+    #[serde(default = "kindle")]
+    source: String,
+    #[serde(with = "highlights_in_order")]
     highlights: BTreeMap<HighlightLocation, Highlight>,
 }
 
+/// books are assumed to come from a Kindle unless their source says otherwise, which includes
+/// libraries saved before the source was recorded
+fn kindle() -> String {
+    "kindle".to_string()
+}
+
+/// highlights are keyed by their location, which formats like JSON can't use as a map key, so
+/// they're written as a list in location order instead
+mod highlights_in_order {
+    use std::collections::BTreeMap;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    use super::{Highlight, HighlightLocation};
+
+    pub fn serialize<S>(
+        highlights: &BTreeMap<HighlightLocation, Highlight>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_seq(highlights.values())
+    }
+
+    pub fn deserialize<'de, D>(
+        deserializer: D,
+    ) -> Result<BTreeMap<HighlightLocation, Highlight>, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Ok(Vec::<Highlight>::deserialize(deserializer)?
+            .into_iter()
+            .map(|h| (h.location().to_owned(), h))
+            .collect())
+    }
+}
+
 impl Book {
     pub fn new(title: String, author: String) -> Self {
         Book {
             title,
             author,
+            source: kindle(),
             highlights: BTreeMap::new(),
         }
     }
@@ -109,6 +223,15 @@ impl Book {
         &self.author
     }
 
+    /// name of the [ClippingSource](crate::source::ClippingSource) the book was imported from
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn set_source(&mut self, source: &str) {
+        self.source = source.to_string();
+    }
+
     pub fn highlights(&self) -> &BTreeMap<HighlightLocation, Highlight> {
         &self.highlights
     }
@@ -1,73 +1,504 @@
-use std::fs::File;
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use chrono::Utc;
+use chrono::{NaiveDateTime, SecondsFormat, Utc};
+use serde::Serialize;
 use tera::{Context, Tera};
+use uuid::Uuid;
 
-use crate::model::{Book, Highlight};
+use crate::filename::DEFAULT_FILE_NAME;
+use crate::filters;
+use crate::model::{Book, Highlight, HighlightLocation};
+use crate::renderer::Renderer;
+use crate::source::source_by_name;
 
-fn now_date() -> String {
+pub(crate) fn now_date() -> String {
     format!("{}", Utc::now().format("%Y-%m-%d"))
 }
 
-/// Uses a [Book] and optionally a [PathBuf] to a custom template file to render the highlights and
-/// notes captured whilst reading to a file in the output [Path].
+/// lowercase, hyphen separated and safe to use in a file name or URL
+pub(crate) fn slugify(s: &str) -> String {
+    s.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect::<Vec<String>>()
+        .join("-")
+}
+
+/// a file name for the title, with the given extension, that isn't already in `used`
+pub(crate) fn unique_file_name(title: &str, extension: &str, used: &mut HashSet<String>) -> String {
+    let slug = match slugify(title) {
+        s if s.is_empty() => "book".to_string(),
+        s => s
+            .chars()
+            .take(80)
+            .collect::<String>()
+            .trim_end_matches('-')
+            .to_string(),
+    };
+
+    let mut name = format!("{}.{}", slug, extension);
+    let mut i = 2;
+    while !used.insert(name.clone()) {
+        name = format!("{}-{}.{}", slug, i, extension);
+        i += 1;
+    }
+
+    name
+}
+
+/// The formats [render_output] can write, each with its own built-in template and file extension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    /// markdown for Obsidian and friends, the original format
+    #[default]
+    Markdown,
+    /// org-mode for Emacs and org-roam
+    Org,
+    /// a markdown outline of blocks for Logseq
+    Logseq,
+}
+
+impl Format {
+    pub const ALL: [Format; 3] = [Format::Markdown, Format::Org, Format::Logseq];
+
+    /// the extension, without a leading dot, of the files written in this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Markdown | Format::Logseq => "md",
+            Format::Org => "org",
+        }
+    }
+
+    /// the name of the format's template, both built in and in a template directory
+    pub(crate) fn template_name(&self) -> &'static str {
+        match self {
+            Format::Markdown => "markdown.md",
+            Format::Org => "org.org",
+            Format::Logseq => "logseq.md",
+        }
+    }
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Markdown => write!(f, "Markdown"),
+            Format::Org => write!(f, "Org-mode"),
+            Format::Logseq => write!(f, "Logseq"),
+        }
+    }
+}
+
+/// a stable id for the book, the same every time it's rendered
+pub fn book_id(book: &Book) -> Uuid {
+    Uuid::new_v5(
+        &Uuid::NAMESPACE_OID,
+        format!("{}\n{}", book.author(), book.title()).as_bytes(),
+    )
+}
+
+/// a stable id for a highlight, derived from its book and location so that references to it
+/// survive re-exports
+pub fn highlight_id(book: &Book, location: &HighlightLocation) -> Uuid {
+    let name = match location.ordinal() {
+        0 => format!("{}-{}", location.start(), location.end()),
+        n => format!("{}-{}-{}", location.start(), location.end(), n),
+    };
+    Uuid::new_v5(&book_id(book), name.as_bytes())
+}
+
+/// A highlight as seen by templates, with its id alongside the highlight's own fields.
+#[derive(Serialize)]
+pub(crate) struct HighlightView<'a> {
+    id: String,
+    #[serde(flatten)]
+    highlight: &'a Highlight,
+}
+
+impl<'a> HighlightView<'a> {
+    pub(crate) fn new(book: &Book, location: &HighlightLocation, highlight: &'a Highlight) -> Self {
+        HighlightView {
+            id: highlight_id(book, location).to_string(),
+            highlight,
+        }
+    }
+
+    pub(crate) fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+/// whether the book's highlight locations are worth showing, see
+/// [ClippingSource::has_locations](crate::source::ClippingSource::has_locations)
+pub(crate) fn has_locations(book: &Book) -> bool {
+    source_by_name(book.source()).map_or(true, |s| s.has_locations())
+}
+
+/// A book as seen by templates, its metadata along with statistics about its highlights.
+#[derive(Serialize)]
+pub(crate) struct BookView<'a> {
+    id: String,
+    title: &'a str,
+    author: &'a str,
+    highlight_count: usize,
+    note_count: usize,
+    first_highlighted: Option<NaiveDateTime>,
+    last_highlighted: Option<NaiveDateTime>,
+    first_page: Option<u64>,
+    last_page: Option<u64>,
+}
+
+impl<'a> BookView<'a> {
+    pub(crate) fn new(book: &'a Book) -> Self {
+        let highlights = book.highlights().values();
+        let dates = highlights.clone().filter_map(|h| *h.date());
+        let pages = highlights.clone().map(|h| *h.page());
+
+        BookView {
+            id: book_id(book).to_string(),
+            title: book.title(),
+            author: book.author(),
+            highlight_count: book.highlights().len(),
+            note_count: highlights.filter(|h| h.note().is_some()).count(),
+            first_highlighted: dates.clone().min(),
+            last_highlighted: dates.max(),
+            first_page: pages.clone().min(),
+            last_page: pages.max(),
+        }
+    }
+}
+
+/// The templates KTR comes with, each available to user templates under `ktr/` followed by its name.
+const BUILT_IN_TEMPLATES: [(&str, &str); 5] = [
+    ("markdown.md", include_str!("templates/default.md")),
+    ("org.org", include_str!("templates/default.org")),
+    ("logseq.md", include_str!("templates/logseq.md")),
+    ("atomic/note.md", include_str!("templates/atomic/note.md")),
+    ("atomic/index.md", include_str!("templates/atomic/index.md")),
+];
+
+/// Uses a [Book] and optionally a [PathBuf] to a custom template file, or a directory of templates,
+/// to render the highlights and notes captured whilst reading to a file in the output [Path].
 ///
-/// Will use a default template if a custom template isn't provided.
+/// A template directory is searched for a template named after the [Format], `markdown.md`,
+/// `org.org` or `logseq.md`, which can include, import or extend any other template in the
+/// directory. Will use the default template for the [Format] if a custom template isn't provided,
+/// or the directory doesn't have one for the format. The file is
+/// named with the `file_name` template, see [filename](crate::filename), and its extension always
+/// follows the format.
 pub fn render_output(
     book: &Book,
     template: &Option<PathBuf>,
+    format: Format,
+    file_name: &str,
     output_dir: &Path,
 ) -> Result<(), RenderError> {
+    Renderer::new(template, format, file_name)?.write(book, output_dir)?;
+    Ok(())
+}
+
+/// render the [Book] as [render_output] would, to any [Write]r, such as stdout
+pub fn render_to<W: Write>(
+    book: &Book,
+    template: &Option<PathBuf>,
+    format: Format,
+    writer: W,
+) -> Result<(), RenderError> {
+    Renderer::new(template, format, DEFAULT_FILE_NAME)?.render_to(book, writer)
+}
+
+/// render the [Book] as [render_output] would, to a [String]
+pub fn render_book(
+    book: &Book,
+    template: &Option<PathBuf>,
+    format: Format,
+) -> Result<String, RenderError> {
+    Renderer::new(template, format, DEFAULT_FILE_NAME)?.render(book)
+}
+
+/// create any directories a file name template asked for
+pub(crate) fn create_parent_dir(file_path: &Path) -> Result<(), RenderError> {
+    match file_path.parent() {
+        Some(parent) => fs::create_dir_all(parent)
+            .map_err(|e| RenderError::CreateOutputFileFailed(e.to_string())),
+        None => Ok(()),
+    }
+}
+
+/// how many temporary files [write_atomically] has created, to keep their names apart
+static TEMP_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Writes a file without ever leaving it half written: `write` fills a temporary file next to it,
+/// which is flushed to disk and renamed over the file only if `write` succeeds. If `backup` is set,
+/// the file's previous version is kept alongside it with `.bak` on the end of its name.
+pub(crate) fn write_atomically<F>(
+    file_path: &Path,
+    backup: bool,
+    write: F,
+) -> Result<(), RenderError>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<(), RenderError>,
+{
+    let name = file_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    // unique to the write, as books rendered in parallel can share a file name
+    let temp_path = file_path.with_file_name(format!(
+        ".{}.{}-{}.tmp",
+        name,
+        std::process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let file =
+        File::create(&temp_path).map_err(|e| RenderError::CreateOutputFileFailed(e.to_string()))?;
+
+    let mut writer = BufWriter::new(file);
+    let result = write(&mut writer).and_then(|_| {
+        let file = writer
+            .into_inner()
+            .map_err(|e| RenderError::WriteFailed(e.to_string()))?;
+        file.sync_all()
+            .map_err(|e| RenderError::WriteFailed(e.to_string()))?;
+
+        if backup && file_path.exists() {
+            fs::copy(file_path, file_path.with_file_name(format!("{}.bak", name)))
+                .map_err(|e| RenderError::WriteFailed(format!("Unable to back up: {}", e)))?;
+        }
+
+        fs::rename(&temp_path, file_path).map_err(|e| RenderError::WriteFailed(e.to_string()))
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    result
+}
+
+/// KTR's filters and templates along with the user's, and the name of the one to render: the user's
+/// template file, the `entry` template from their template directory, or the built-in `entry`
+pub(crate) fn load_templates(
+    template: &Option<PathBuf>,
+    entry: &str,
+) -> Result<(Tera, String), RenderError> {
     let mut tera = Tera::default();
-    tera.add_raw_template("default", include_str!("templates/default.md"))
-        .unwrap();
+    filters::register(&mut tera);
+    tera.add_raw_templates(
+        BUILT_IN_TEMPLATES
+            .iter()
+            .map(|(name, content)| (format!("ktr/{}", name), *content)),
+    )
+    .unwrap();
 
-    let mut ctx = Context::new();
-    ctx.insert("date", &now_date());
+    match template {
+        Some(dir) if dir.is_dir() => {
+            let mut files: Vec<(PathBuf, String)> = Vec::new();
+            template_files(dir, dir, &mut files)?;
+            tera.add_template_files(
+                files
+                    .iter()
+                    .map(|(path, name)| (path, Some(name.as_str())))
+                    .collect::<Vec<(&PathBuf, Option<&str>)>>(),
+            )
+            .map_err(|e| RenderError::ParsingFailed(tera_error(&e)))?;
+
+            let name = template_name(&tera, entry);
+            Ok((tera, name))
+        }
+        Some(file) => {
+            tera.add_template_file(file, Some("user"))
+                .map_err(|e| RenderError::ParsingFailed(tera_error(&e)))?;
+            Ok((tera, "user".to_string()))
+        }
+        None => Ok((tera, format!("ktr/{}", entry))),
+    }
+}
+
+/// the error with everything Tera says caused it, which is where the line numbers of syntax
+/// errors are
+pub(crate) fn tera_error(e: &tera::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = std::error::Error::source(e);
+    while let Some(cause) = source {
+        message.push('\n');
+        message.push_str(cause.to_string().trim_end());
+        source = cause.source();
+    }
+
+    message
+}
+
+/// `entry` if the user's template directory has it, otherwise the built-in template
+pub(crate) fn template_name(tera: &Tera, entry: &str) -> String {
+    match tera.get_template_names().any(|n| n == entry) {
+        true => entry.to_string(),
+        false => format!("ktr/{}", entry),
+    }
+}
+
+/// the extensions of the files in a template directory that are loaded as templates, so READMEs,
+/// images and the like can sit alongside them
+const TEMPLATE_EXTENSIONS: [&str; 5] = ["md", "org", "html", "txt", "tera"];
+
+/// every template file under `dir`, other than hidden ones and those that aren't text, named by its
+/// path from `root` with `/` between its parts
+fn template_files(
+    root: &Path,
+    dir: &Path,
+    files: &mut Vec<(PathBuf, String)>,
+) -> Result<(), RenderError> {
+    let read_error = |e: std::io::Error| {
+        RenderError::ParsingFailed(format!(
+            "Unable to read templates from {}: {}",
+            dir.display(),
+            e
+        ))
+    };
+
+    for entry in fs::read_dir(dir).map_err(read_error)? {
+        let path = entry.map_err(read_error)?.path();
+        if path
+            .file_name()
+            .is_some_and(|n| n.to_string_lossy().starts_with('.'))
+        {
+            continue;
+        }
+
+        if path.is_dir() {
+            template_files(root, &path, files)?;
+            continue;
+        }
+
+        let is_template = path.extension().is_some_and(|e| {
+            TEMPLATE_EXTENSIONS
+                .iter()
+                .any(|t| e.eq_ignore_ascii_case(t))
+        });
+        if !is_template || fs::read_to_string(&path).is_err() {
+            continue;
+        }
+
+        if let Ok(relative) = path.strip_prefix(root) {
+            let name = relative
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            files.push((path, name));
+        }
+    }
+
+    Ok(())
+}
+
+pub(crate) fn book_context(book: &Book) -> Context {
+    let mut ctx = base_context(book);
     ctx.insert(
         "highlights",
         &book
             .highlights()
-            .values()
-            .cloned()
-            .collect::<Vec<Highlight>>(),
+            .iter()
+            .map(|(location, highlight)| HighlightView::new(book, location, highlight))
+            .collect::<Vec<HighlightView>>(),
     );
     ctx.insert("quotes", &book.quotes());
 
-    let output: tera::Result<()>;
+    ctx
+}
+
+/// the book's metadata and when and by what it was rendered, shared by every per book template
+pub(crate) fn base_context(book: &Book) -> Context {
+    let mut ctx = Context::new();
+    ctx.insert("date", &now_date());
+    ctx.insert("id", &book_id(book).to_string());
+    ctx.insert("title", book.title());
+    ctx.insert("author", book.author());
+    ctx.insert("source", book.source());
+    ctx.insert("has_locations", &has_locations(book));
+    ctx.insert("book", &BookView::new(book));
+    ctx.insert(
+        "generated_at",
+        &Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+    );
+    ctx.insert("ktr_version", env!("CARGO_PKG_VERSION"));
+
+    ctx
+}
+
+/// name of the file written by [render_csv]
+pub const CSV_FILE_NAME: &str = "highlights.csv";
+
+/// Writes every highlight in the library to a `highlights.csv` file in the output [Path], one row
+/// per highlight, for use in a spreadsheet.
+pub fn render_csv(library: &HashMap<String, Book>, output_dir: &Path) -> Result<(), RenderError> {
     let mut file_path = output_dir.to_path_buf();
-    file_path.push(format!("{}. {}.md", book.author(), book.title()));
+    file_path.push(CSV_FILE_NAME);
 
     let file: File = match File::create(file_path.as_path()) {
         Ok(f) => f,
-        Err(e) => {
-            return Err(RenderError::CreateOutputFileFailed(e.to_string()));
-            // eprintln!("Error creating output file: {}", e);
-            // ::std::process::exit(1);
-        }
+        Err(e) => return Err(RenderError::CreateOutputFileFailed(e.to_string())),
     };
 
-    // use default template unless user has provided one
-    if let Some(t) = template {
-        tera.add_template_file(t, Some("user")).unwrap();
-        output = tera.render_to("user", &ctx, file);
-    } else {
-        output = tera.render_to("default", &ctx, file);
-    }
+    export_csv(library, file)
+}
+
+/// Writes every highlight in the library to `writer` as CSV with a header row. Books are ordered
+/// by title and highlights by location.
+pub fn export_csv<W>(library: &HashMap<String, Book>, writer: W) -> Result<(), RenderError>
+where
+    W: Write,
+{
+    let mut books: Vec<&Book> = library.values().collect();
+    books.sort_by(|a, b| a.title().cmp(b.title()));
 
-    if let Err(e) = output {
-        return Err(RenderError::ParsingFailed(e.to_string()));
-        // eprintln!("Parsing error(s): {}", e);
-        // ::std::process::exit(1);
+    let mut csv = csv::Writer::from_writer(writer);
+    let to_error = |e: csv::Error| RenderError::WriteFailed(e.to_string());
+
+    csv.write_record([
+        "title",
+        "author",
+        "page",
+        "location_start",
+        "location_end",
+        "timestamp",
+        "quote",
+        "note",
+    ])
+    .map_err(to_error)?;
+
+    for book in books {
+        for hl in book.highlights().values() {
+            csv.write_record([
+                book.title(),
+                book.author(),
+                &hl.page().to_string(),
+                &hl.location().start().to_string(),
+                &hl.location().end().to_string(),
+                &hl.date()
+                    .map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string())
+                    .unwrap_or_default(),
+                hl.quote().trim(),
+                hl.note().as_ref().map_or("", |n| n.content()),
+            ])
+            .map_err(to_error)?;
+        }
     }
 
-    Ok(())
+    csv.flush()
+        .map_err(|e| RenderError::WriteFailed(e.to_string()))
 }
 
+#[derive(Debug)]
 pub enum RenderError {
     CreateOutputFileFailed(String),
     ParsingFailed(String),
+    WriteFailed(String),
+    ReadExistingFailed(String),
 }
 
 impl std::fmt::Display for RenderError {
@@ -75,6 +506,247 @@ impl std::fmt::Display for RenderError {
         match self {
             RenderError::CreateOutputFileFailed(s) => write!(f, "{}", s),
             RenderError::ParsingFailed(s) => write!(f, "{}", s),
+            RenderError::WriteFailed(s) => write!(f, "{}", s),
+            RenderError::ReadExistingFailed(s) => write!(f, "{}", s),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::env;
+    use std::fs;
+
+    use chrono::{NaiveDate, Utc};
+
+    use crate::filename::DEFAULT_FILE_NAME;
+    use crate::model::{Book, Highlight, HighlightLocation, Note};
+    use crate::output::{
+        book_id, export_csv, highlight_id, render_book, render_output, render_to, Format,
+    };
+
+    #[test]
+    fn csv_quoting() {
+        let mut book = Book::new("Commas, Quotes".to_string(), "Doe, Jane".to_string());
+        book.add_highlight(Highlight::new(
+            3,
+            HighlightLocation::new(40, 42),
+            "A \"quoted\", multi\nline quote".to_string(),
+        ));
+        book.add_note(Note::new(3, 41, "A note".to_string()));
+
+        let mut library = HashMap::new();
+        library.insert(book.title().to_string(), book);
+
+        let mut out: Vec<u8> = Vec::new();
+        export_csv(&library, &mut out).unwrap();
+
+        let mut reader = csv::Reader::from_reader(out.as_slice());
+        let rows: Vec<csv::StringRecord> = reader.records().map(|r| r.unwrap()).collect();
+
+        assert_eq!(1, rows.len());
+        assert_eq!(
+            vec![
+                "Commas, Quotes",
+                "Doe, Jane",
+                "3",
+                "40",
+                "42",
+                "",
+                "A \"quoted\", multi\nline quote",
+                "A note"
+            ],
+            rows[0].iter().collect::<Vec<&str>>()
+        );
+    }
+
+    #[test]
+    fn in_memory() {
+        let mut book = Book::new("A Book".to_string(), "Doe, Jane".to_string());
+        book.add_highlight(Highlight::new(
+            3,
+            HighlightLocation::new(40, 42),
+            "A quote".to_string(),
+        ));
+
+        let dir = env::temp_dir().join(format!("ktr-memory-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        render_output(&book, &None, Format::Markdown, DEFAULT_FILE_NAME, &dir).unwrap();
+        let file = fs::read_to_string(dir.join("Doe, Jane. A Book.md")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut out: Vec<u8> = Vec::new();
+        render_to(&book, &None, Format::Markdown, &mut out).unwrap();
+
+        assert_eq!(file, String::from_utf8(out).unwrap());
+        assert_eq!(file, render_book(&book, &None, Format::Markdown).unwrap());
+    }
+
+    #[test]
+    fn sources() {
+        let mut book = Book::new("A Book".to_string(), "Doe, Jane".to_string());
+        let kindle = render_book(&book, &None, Format::Markdown).unwrap();
+        book.set_source("readwise");
+        let readwise = render_book(&book, &None, Format::Markdown).unwrap();
+
+        assert!(kindle.contains("\ntype: kindle\n"));
+        assert!(readwise.contains("\ntype: readwise\n"));
+
+        // the made up locations of sources without any aren't shown
+        book.add_highlight(Highlight::new(
+            3,
+            HighlightLocation::new(30_000_040, 30_000_040),
+            "A quote".to_string(),
+        ));
+        book.set_source("pdf");
+        let org = render_book(&book, &None, Format::Org).unwrap();
+        let logseq = render_book(&book, &None, Format::Logseq).unwrap();
+
+        assert!(org.contains("\n* Page 3\n"));
+        assert!(logseq.contains("  page:: 3\n#+BEGIN_COMMENT\n"));
+        assert!(!org.contains("30000040") && !logseq.contains("30000040"));
+    }
+
+    #[test]
+    fn book_context() {
+        let mut book = Book::new("A Book".to_string(), "Doe, Jane".to_string());
+        let mut first = Highlight::new(12, HighlightLocation::new(40, 42), "First".to_string());
+        first.add_date(
+            NaiveDate::from_ymd_opt(2024, 3, 1)
+                .unwrap()
+                .and_hms_opt(9, 30, 0)
+                .unwrap(),
+        );
+        book.add_highlight(first);
+        book.add_highlight(Highlight::new(
+            3,
+            HighlightLocation::new(10, 10),
+            "Second".to_string(),
+        ));
+        book.add_note(Note::new(3, 10, "A note".to_string()));
+
+        let dir = env::temp_dir().join(format!("ktr-context-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let template = dir.join("template.md");
+        fs::write(
+            &template,
+            "{{ book.title }} by {{ book.author }}: {{ book.highlight_count }} highlights, \
+             {{ book.note_count }} note(s), pages {{ book.first_page }}-{{ book.last_page }}, \
+             {{ book.first_highlighted }}, {{ book.last_highlighted }}, ktr {{ ktr_version }}, \
+             {{ generated_at }}",
+        )
+        .unwrap();
+        let rendered = render_book(&book, &Some(template), Format::Markdown);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(rendered.unwrap().starts_with(&format!(
+            "A Book by Doe, Jane: 2 highlights, 1 note(s), pages 3-12, 2024-03-01T09:30:00, \
+             2024-03-01T09:30:00, ktr {}, {}T",
+            env!("CARGO_PKG_VERSION"),
+            Utc::now().format("%Y-%m-%d")
+        )));
+    }
+
+    #[test]
+    fn template_directory() {
+        let mut book = Book::new("A Book".to_string(), "Doe, Jane".to_string());
+        book.add_highlight(Highlight::new(
+            3,
+            HighlightLocation::new(40, 42),
+            "A quote".to_string(),
+        ));
+
+        let dir = env::temp_dir().join(format!("ktr-templates-{}", std::process::id()));
+        fs::create_dir_all(dir.join("partials")).unwrap();
+        fs::write(
+            dir.join("base.md"),
+            "# {{ title }}\n{% block highlights %}{% endblock highlights %}",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("partials/highlight.md"),
+            "> {{ hl.quote }} (p. {{ hl.page }})\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("markdown.md"),
+            "{% extends \"base.md\" %}{% block highlights %}\
+             {% for hl in highlights %}{% include \"partials/highlight.md\" %}{% endfor %}\
+             {% endblock highlights %}",
+        )
+        .unwrap();
+        fs::write(dir.join(".ignored.md"), "{% broken").unwrap();
+        fs::write(dir.join("cover.png"), [0x89, b'P', b'N', b'G', 0xff, 0xfe]).unwrap();
+        fs::write(dir.join("notes.txt"), [b'{', b'{', 0xff, 0xfe]).unwrap();
+        fs::write(dir.join("LICENSE"), "{% not a template").unwrap();
+
+        let template = Some(dir.clone());
+        let markdown = render_book(&book, &template, Format::Markdown);
+        let org = render_book(&book, &template, Format::Org);
+        let default_org = render_book(&book, &None, Format::Org);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!("# A Book\n> A quote (p. 3)\n", markdown.unwrap());
+        // there's no org.org, so the built-in template is used
+        assert_eq!(default_org.unwrap(), org.unwrap());
+    }
+
+    #[test]
+    fn org() {
+        let mut book = Book::new("A Book".to_string(), "Doe, Jane".to_string());
+        let mut highlight =
+            Highlight::new(3, HighlightLocation::new(40, 42), "A quote\n".to_string());
+        highlight.add_tag("ideas".to_string());
+        book.add_highlight(highlight);
+        book.add_note(Note::new(3, 41, "A note".to_string()));
+        book.set_source("readwise");
+
+        let dir = env::temp_dir().join(format!("ktr-org-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        render_output(&book, &None, Format::Org, DEFAULT_FILE_NAME, &dir).unwrap();
+        let org = fs::read_to_string(dir.join("Doe, Jane. A Book.org")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(org.starts_with(&format!(":PROPERTIES:\n:ID:       {}\n", book_id(&book))));
+        assert!(org.contains(":TYPE:     readwise\n"));
+        assert!(org.contains("#+title: A Book\n"));
+        assert!(org.contains("#+filetags: :readwise:\n"));
+        assert!(org.contains(&format!(
+            "* Location 40-42\n:PROPERTIES:\n:ID:       {}\n:PAGE:     3\n:TAGS:     ideas\n\
+             :END:\n#+begin_quote\nA quote\n#+end_quote\n\n** Note\nA note\n",
+            highlight_id(&book, &HighlightLocation::new(40, 42))
+        )));
+    }
+
+    #[test]
+    fn logseq() {
+        let mut book = Book::new("A Book".to_string(), "Doe, Jane".to_string());
+        book.add_highlight(Highlight::new(
+            3,
+            HighlightLocation::new(40, 42),
+            "A quote\nover two lines".to_string(),
+        ));
+        book.add_note(Note::new(3, 41, "A note".to_string()));
+
+        let id = highlight_id(&book, &HighlightLocation::new(40, 42));
+        assert_eq!(id, highlight_id(&book, &HighlightLocation::new(40, 42)));
+        assert_ne!(id, highlight_id(&book, &HighlightLocation::new(40, 43)));
+        assert_ne!(
+            id,
+            highlight_id(&book, &HighlightLocation::with_ordinal(40, 42, 1))
+        );
+
+        let dir = env::temp_dir().join(format!("ktr-logseq-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        render_output(&book, &None, Format::Logseq, DEFAULT_FILE_NAME, &dir).unwrap();
+        let page = fs::read_to_string(dir.join("Doe, Jane. A Book.md")).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(page.starts_with("title:: A Book\nauthor:: [[Doe, Jane]]\n"));
+        assert!(page.contains(&format!(
+            "- A quote\n  over two lines\n  id:: {}\n  page:: 3\n  location:: 40-42\n\t- A note\n",
+            id
+        )));
+    }
+}
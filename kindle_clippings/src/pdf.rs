@@ -0,0 +1,874 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDateTime;
+use lopdf::content::Content;
+use lopdf::{decode_text_string, Dictionary, Document, Encoding, Object, ObjectId};
+
+use crate::model::{Book, Highlight, HighlightLocation, Note};
+use crate::source::{has_extension, ClippingSource, Diagnostic, Import, SourceError};
+
+/// Highlight and text annotations made in local PDF files.
+///
+/// Either a single PDF or a directory of PDFs can be imported. Each PDF becomes a [Book], titled
+/// and attributed from the document information, falling back to the file name.
+pub struct Pdf;
+
+impl ClippingSource for Pdf {
+    fn name(&self) -> &'static str {
+        "pdf"
+    }
+
+    fn accepts(&self, path: &Path) -> bool {
+        has_extension(path, &["pdf"])
+    }
+
+    fn has_locations(&self) -> bool {
+        false
+    }
+
+    fn import(&self, path: &Path) -> Result<Import, SourceError> {
+        let files: Vec<PathBuf> = if path.is_dir() {
+            let mut files: Vec<PathBuf> = std::fs::read_dir(path)
+                .map_err(|e| SourceError::ReadFailed(e.to_string()))?
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.is_file() && self.accepts(p))
+                .collect();
+            files.sort();
+            files
+        } else {
+            vec![path.to_path_buf()]
+        };
+
+        let mut import = Import::default();
+
+        for file in files.iter() {
+            let book = match parse_pdf(file) {
+                Ok((book, diagnostics)) => {
+                    import.diagnostics.extend(diagnostics);
+                    book
+                }
+                // a single bad file shouldn't stop a whole directory from being read
+                Err(e) if files.len() > 1 => {
+                    import
+                        .diagnostics
+                        .push(Diagnostic::new(None, format!("{}: {}", file.display(), e)));
+                    continue;
+                }
+                Err(e) => return Err(e),
+            };
+
+            if book.highlights().is_empty() {
+                continue;
+            }
+
+            if import.library.contains_key(book.title()) {
+                import.diagnostics.push(Diagnostic::new(
+                    None,
+                    format!(
+                        "{}: a book titled '{}' has already been read",
+                        file.display(),
+                        book.title()
+                    ),
+                ));
+                continue;
+            }
+
+            import.library.insert(book.title().to_string(), book);
+        }
+
+        Ok(import)
+    }
+}
+
+/// read the highlight annotations of a PDF into a [Book]
+///
+/// The page number of each highlight is the number in the document's page labels, the one a
+/// reader shows, or its position in the document when there are no labels. The location comes
+/// from the page's position and where the highlight starts on the page, which only keeps the
+/// highlights in order, so [Pdf] doesn't [have locations](ClippingSource::has_locations) worth
+/// showing. Comments made on a highlight, either directly, through its popup or as replies, become
+/// its note. Sticky notes that aren't replies are attached to the closest highlight above them on
+/// the same page, or the first highlight on the page when there's nothing above.
+///
+/// Highlights whose text is drawn in a font that doesn't give the width of each character, as
+/// with most fonts embedded by modern software, can't be matched to their text reliably. The text
+/// the annotation stores is used for them if there is any, otherwise they're skipped with a
+/// [Diagnostic].
+pub fn parse_pdf(path: &Path) -> Result<(Book, Vec<Diagnostic>), SourceError> {
+    let doc = Document::load(path).map_err(|e| SourceError::ReadFailed(e.to_string()))?;
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    let title = info_string(&doc, b"Title").unwrap_or_else(|| {
+        path.file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default()
+    });
+    let author = info_string(&doc, b"Author").unwrap_or_else(|| "Unknown".to_string());
+
+    let mut book = Book::new(title, author);
+    book.set_source("pdf");
+    let labels = page_labels(&doc);
+
+    for (page, page_id) in doc.get_pages() {
+        let label = page_number(&labels, page);
+        let mut annotations = page_annotations(&doc, page_id);
+        if annotations.is_empty() {
+            continue;
+        }
+
+        // top of the page first, then left to right
+        annotations.sort_by(|(_, a), (_, b)| {
+            let (ax, ay) = top_left(a);
+            let (bx, by) = top_left(b);
+            by.total_cmp(&ay).then(ax.total_cmp(&bx))
+        });
+
+        let text = page_text(&doc, page_id);
+
+        // annotation id, top edge and location of each highlight on this page
+        let mut highlights: Vec<(ObjectId, f32, u64)> = Vec::new();
+        let mut comments: BTreeMap<u64, Vec<String>> = BTreeMap::new();
+
+        for (id, annotation) in annotations.iter() {
+            if !subtype_is(annotation, b"Highlight") {
+                continue;
+            }
+
+            let rects = quad_rects(annotation);
+            let placed = text_in_rects(&text, &rects);
+            let mut quote = placed.clone().unwrap_or_default();
+            let contents = annotation_string(annotation, b"Contents");
+
+            let mut comment = contents.clone();
+            if quote.is_empty() {
+                // some readers store the highlighted text as the annotation's contents
+                match contents {
+                    Some(c) => {
+                        quote = c;
+                        comment = None;
+                    }
+                    None => {
+                        let problem = match placed {
+                            Some(_) => "highlight with no readable text",
+                            // rather than a quote that may be made of the wrong characters
+                            None => "highlight in a font that doesn't give its character widths, so its text can't be picked out",
+                        };
+                        diagnostics.push(Diagnostic::new(
+                            Some(page as usize),
+                            format!("{}: {}", path.display(), problem),
+                        ));
+                        continue;
+                    }
+                }
+            }
+
+            if comment.is_none() {
+                comment = annotation
+                    .get(b"Popup")
+                    .and_then(Object::as_reference)
+                    .and_then(|id| doc.get_dictionary(id))
+                    .ok()
+                    .and_then(|popup| annotation_string(popup, b"Contents"))
+                    .filter(|c| *c != quote);
+            }
+
+            // highlights in exactly the same place are told apart by the order they're read in
+            let mut location = pdf_location(page, annotation);
+            while book
+                .highlights()
+                .contains_key(&HighlightLocation::new(location, location))
+            {
+                location += 1;
+            }
+
+            let mut highlight =
+                Highlight::new(label, HighlightLocation::new(location, location), quote);
+
+            if let Some(date) = annotation_date(annotation) {
+                highlight.add_date(date);
+            }
+
+            book.add_highlight(highlight);
+            highlights.push((*id, top_left(annotation).1, location));
+
+            if let Some(c) = comment {
+                comments.entry(location).or_default().push(c);
+            }
+        }
+
+        for (_, annotation) in annotations.iter() {
+            if !subtype_is(annotation, b"Text") {
+                continue;
+            }
+
+            let Some(content) = annotation_string(annotation, b"Contents") else {
+                continue;
+            };
+
+            // replies point at the annotation they're replying to, sticky notes don't
+            let target = match annotation.get(b"IRT").and_then(Object::as_reference) {
+                Ok(parent) => highlights.iter().find(|(id, _, _)| *id == parent),
+                Err(_) => {
+                    let y = top_left(annotation).1;
+                    highlights
+                        .iter()
+                        .rfind(|(_, top, _)| *top >= y)
+                        .or(highlights.first())
+                }
+            };
+
+            match target {
+                Some((_, _, loc)) => comments.entry(*loc).or_default().push(content),
+                None => diagnostics.push(Diagnostic::new(
+                    Some(page as usize),
+                    format!(
+                        "{}: note on page {} has no highlight to attach to",
+                        path.display(),
+                        page
+                    ),
+                )),
+            }
+        }
+
+        for (loc, content) in comments {
+            book.add_note(Note::new(label, loc, content.join(" ")));
+        }
+    }
+
+    Ok((book, diagnostics))
+}
+
+/// how many locations each page has, [pdf_location] keeps the locations on a page below this
+const PAGE_LOCATIONS: u64 = 10_000_000;
+
+/// a location that stays the same however many highlights are added to the document: the page's
+/// position scaled up, plus the highlight's top edge and then its left edge, in points
+fn pdf_location(page: u32, annotation: &Dictionary) -> u64 {
+    let (x, y) = top_left(annotation);
+    let down = (9_999.0 - y.round()).clamp(0.0, 9_999.0) as u64;
+    let across = x.round().clamp(0.0, 999.0) as u64;
+
+    page as u64 * PAGE_LOCATIONS + down * 1_000 + across
+}
+
+/// The first page of a range in the document's `/PageLabels`, counted from 0, and the number
+/// its label starts at, if the labels in the range are numbered.
+type LabelRange = (u32, Option<u64>);
+
+/// the ranges of the document's page labels, in page order
+fn page_labels(doc: &Document) -> Vec<LabelRange> {
+    let mut ranges: Vec<LabelRange> = Vec::new();
+
+    if let Ok(tree) = doc
+        .catalog()
+        .and_then(|c| c.get_deref(b"PageLabels", doc))
+        .and_then(Object::as_dict)
+    {
+        label_ranges(doc, tree, &mut ranges);
+    }
+
+    ranges.sort_by_key(|(start, _)| *start);
+    ranges
+}
+
+/// the ranges in a node of the page labels number tree and its children
+fn label_ranges(doc: &Document, node: &Dictionary, ranges: &mut Vec<LabelRange>) {
+    if let Ok(nums) = node.get_deref(b"Nums", doc).and_then(Object::as_array) {
+        for pair in nums.chunks_exact(2) {
+            let Ok(start) = pair[0].as_i64() else {
+                continue;
+            };
+            let label = match &pair[1] {
+                Object::Reference(id) => doc.get_dictionary(*id).ok(),
+                other => other.as_dict().ok(),
+            };
+
+            // a label without a numbering style is only its prefix
+            let first = label.filter(|l| l.has(b"S")).map(|l| {
+                l.get(b"St")
+                    .and_then(Object::as_i64)
+                    .map_or(1, |st| st.max(0) as u64)
+            });
+            ranges.push((start.max(0) as u32, first));
+        }
+    }
+
+    if let Ok(kids) = node.get_deref(b"Kids", doc).and_then(Object::as_array) {
+        for kid in kids.iter() {
+            if let Ok(kid) = kid.as_reference().and_then(|id| doc.get_dictionary(id)) {
+                label_ranges(doc, kid, ranges);
+            }
+        }
+    }
+}
+
+/// the number on the page's label, or its position in the document if it isn't numbered
+fn page_number(labels: &[LabelRange], page: u32) -> u64 {
+    let index = page.saturating_sub(1);
+
+    match labels.iter().rfind(|(start, _)| *start <= index) {
+        Some((start, Some(first))) => first + (index - start) as u64,
+        _ => page as u64,
+    }
+}
+
+/// a character drawn on the page and the point at its centre, in default user space
+struct Glyph {
+    text: String,
+    x: f32,
+    y: f32,
+    /// whether this glyph starts a new text showing operation
+    starts_run: bool,
+    /// whether a glyph width had to be guessed since the text position was last set, in which
+    /// case this glyph may be drawn somewhere else entirely
+    guessed: bool,
+}
+
+/// [a b c d e f] as used by PDF content streams
+type Matrix = [f32; 6];
+
+const IDENTITY: Matrix = [1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+
+/// apply `m1` then `m2`
+fn multiply(m1: &Matrix, m2: &Matrix) -> Matrix {
+    [
+        m1[0] * m2[0] + m1[1] * m2[2],
+        m1[0] * m2[1] + m1[1] * m2[3],
+        m1[2] * m2[0] + m1[3] * m2[2],
+        m1[2] * m2[1] + m1[3] * m2[3],
+        m1[4] * m2[0] + m1[5] * m2[2] + m2[4],
+        m1[4] * m2[1] + m1[5] * m2[3] + m2[5],
+    ]
+}
+
+fn transform(m: &Matrix, x: f32, y: f32) -> (f32, f32) {
+    (m[0] * x + m[2] * y + m[4], m[1] * x + m[3] * y + m[5])
+}
+
+fn translate(tx: f32, ty: f32) -> Matrix {
+    [1.0, 0.0, 0.0, 1.0, tx, ty]
+}
+
+struct PageFont<'a> {
+    encoding: Encoding<'a>,
+    first_char: i64,
+    widths: Vec<f32>,
+}
+
+/// width used for glyphs the font doesn't give one for, in thousandths of text space
+const GUESSED_WIDTH: f32 = 500.0;
+
+impl PageFont<'_> {
+    /// glyph width in thousandths of text space, if the font says
+    ///
+    /// Only the `/Widths` of simple fonts are read, so the widths of composite (Type0) fonts, which
+    /// most modern PDFs embed, are never known.
+    fn width(&self, code: Option<u8>) -> Option<f32> {
+        code.and_then(|c| {
+            let i = c as i64 - self.first_char;
+            (i >= 0).then(|| self.widths.get(i as usize)).flatten()
+        })
+        .copied()
+    }
+}
+
+fn page_fonts(doc: &Document, page_id: ObjectId) -> HashMap<Vec<u8>, PageFont<'_>> {
+    let mut fonts = HashMap::new();
+
+    for (name, font) in doc.get_page_fonts(page_id).unwrap_or_default() {
+        let Ok(encoding) = font.get_font_encoding(doc) else {
+            continue;
+        };
+
+        let widths = font
+            .get_deref(b"Widths", doc)
+            .and_then(Object::as_array)
+            .map(|w| {
+                w.iter()
+                    .map(|o| o.as_float().unwrap_or(GUESSED_WIDTH))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        fonts.insert(
+            name,
+            PageFont {
+                encoding,
+                first_char: font.get(b"FirstChar").and_then(Object::as_i64).unwrap_or(0),
+                widths,
+            },
+        );
+    }
+
+    fonts
+}
+
+/// walk the page's content stream, keeping track of where each character is drawn
+fn page_text(doc: &Document, page_id: ObjectId) -> Vec<Glyph> {
+    let mut glyphs: Vec<Glyph> = Vec::new();
+
+    let Ok(content) = doc
+        .get_page_content(page_id)
+        .and_then(|c| Content::decode(&c))
+    else {
+        return glyphs;
+    };
+
+    let fonts = page_fonts(doc, page_id);
+
+    let mut ctm = IDENTITY;
+    let mut stack: Vec<Matrix> = Vec::new();
+    let mut tm = IDENTITY;
+    let mut tlm = IDENTITY;
+    let mut font: Option<&PageFont> = None;
+    let mut size: f32 = 0.0;
+    let mut leading: f32 = 0.0;
+    let mut char_spacing: f32 = 0.0;
+    let mut word_spacing: f32 = 0.0;
+    let mut scale: f32 = 1.0;
+    let mut guessing = false;
+
+    let numbers = |operands: &[Object]| -> Vec<f32> {
+        operands.iter().filter_map(|o| o.as_float().ok()).collect()
+    };
+
+    for op in content.operations.iter() {
+        let n = numbers(&op.operands);
+
+        match op.operator.as_str() {
+            "q" => stack.push(ctm),
+            "Q" => ctm = stack.pop().unwrap_or(IDENTITY),
+            "cm" if n.len() == 6 => ctm = multiply(&[n[0], n[1], n[2], n[3], n[4], n[5]], &ctm),
+            "BT" => {
+                tm = IDENTITY;
+                tlm = IDENTITY;
+                guessing = false;
+            }
+            "Tf" => {
+                font = op
+                    .operands
+                    .first()
+                    .and_then(|o| o.as_name().ok())
+                    .and_then(|name| fonts.get(name));
+                size = n.first().copied().unwrap_or(size);
+            }
+            "TL" => leading = n.first().copied().unwrap_or(leading),
+            "Tc" => char_spacing = n.first().copied().unwrap_or(char_spacing),
+            "Tw" => word_spacing = n.first().copied().unwrap_or(word_spacing),
+            "Tz" => scale = n.first().map_or(scale, |s| s / 100.0),
+            "Td" | "TD" if n.len() == 2 => {
+                if op.operator == "TD" {
+                    leading = -n[1];
+                }
+                tlm = multiply(&translate(n[0], n[1]), &tlm);
+                tm = tlm;
+                guessing = false;
+            }
+            "Tm" if n.len() == 6 => {
+                tlm = [n[0], n[1], n[2], n[3], n[4], n[5]];
+                tm = tlm;
+                guessing = false;
+            }
+            "T*" | "'" | "\"" => {
+                if op.operator == "\"" && n.len() >= 2 {
+                    word_spacing = n[0];
+                    char_spacing = n[1];
+                }
+                tlm = multiply(&translate(0.0, -leading), &tlm);
+                tm = tlm;
+                guessing = false;
+            }
+            _ => {}
+        }
+
+        if !matches!(op.operator.as_str(), "Tj" | "TJ" | "'" | "\"") {
+            continue;
+        }
+
+        let Some(font) = font else {
+            continue;
+        };
+
+        let mut starts_run = true;
+        let mut show = |bytes: &[u8], tm: &mut Matrix| {
+            let one_byte = matches!(font.encoding, Encoding::OneByteEncoding(_));
+            let chars: Vec<(String, Option<u8>)> = if one_byte {
+                bytes
+                    .iter()
+                    .map(|b| {
+                        let s = Document::decode_text(&font.encoding, &[*b]).unwrap_or_default();
+                        (s, Some(*b))
+                    })
+                    .collect()
+            } else {
+                Document::decode_text(&font.encoding, bytes)
+                    .unwrap_or_default()
+                    .chars()
+                    .map(|c| (c.to_string(), None))
+                    .collect()
+            };
+
+            for (text, code) in chars {
+                let width = font.width(code);
+                guessing |= width.is_none();
+
+                let mut advance = width.unwrap_or(GUESSED_WIDTH) / 1000.0 * size + char_spacing;
+                if text == " " {
+                    advance += word_spacing;
+                }
+                advance *= scale;
+
+                let (x, y) = transform(&multiply(tm, &ctm), advance / 2.0, size * 0.3);
+                glyphs.push(Glyph {
+                    text,
+                    x,
+                    y,
+                    starts_run,
+                    guessed: guessing,
+                });
+                starts_run = false;
+
+                *tm = multiply(&translate(advance, 0.0), tm);
+            }
+        };
+
+        for operand in op.operands.iter() {
+            match operand {
+                Object::String(bytes, _) => show(bytes, &mut tm),
+                Object::Array(items) => {
+                    for item in items {
+                        match item {
+                            Object::String(bytes, _) => show(bytes, &mut tm),
+                            other => {
+                                if let Ok(adjust) = other.as_float() {
+                                    let tx = -adjust / 1000.0 * size * scale;
+                                    tm = multiply(&translate(tx, 0.0), &tm);
+                                }
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    glyphs
+}
+
+/// join the characters drawn within any of the rectangles, in the order they were drawn, or [None]
+/// if where any of the characters on those lines were drawn is a guess
+fn text_in_rects(glyphs: &[Glyph], rects: &[[f32; 4]]) -> Option<String> {
+    let mut out = String::new();
+    let mut last: Option<&Glyph> = None;
+
+    for glyph in glyphs.iter() {
+        let inside = rects.iter().any(|[x1, y1, x2, y2]| {
+            glyph.x >= *x1 && glyph.x <= *x2 && glyph.y >= *y1 && glyph.y <= *y2
+        });
+
+        if !inside {
+            continue;
+        }
+        if glyph.guessed {
+            return None;
+        }
+
+        // separate words that were drawn by different operations, e.g. across a line break
+        if let Some(prev) = last {
+            if glyph.starts_run && (prev.y - glyph.y).abs() > 1.0 && !out.ends_with(' ') {
+                out.push(' ');
+            }
+        }
+
+        out.push_str(&glyph.text);
+        last = Some(glyph);
+    }
+
+    Some(out.split_whitespace().collect::<Vec<&str>>().join(" "))
+}
+
+/// the areas covered by a highlight, as [x1, y1, x2, y2]
+fn quad_rects(annotation: &Dictionary) -> Vec<[f32; 4]> {
+    let points: Vec<f32> = annotation
+        .get(b"QuadPoints")
+        .and_then(Object::as_array)
+        .map(|a| a.iter().filter_map(|o| o.as_float().ok()).collect())
+        .unwrap_or_default();
+
+    if points.len() >= 8 {
+        points
+            .chunks_exact(8)
+            .map(|q| {
+                let xs = [q[0], q[2], q[4], q[6]];
+                let ys = [q[1], q[3], q[5], q[7]];
+                [
+                    xs.iter().copied().fold(f32::MAX, f32::min),
+                    ys.iter().copied().fold(f32::MAX, f32::min),
+                    xs.iter().copied().fold(f32::MIN, f32::max),
+                    ys.iter().copied().fold(f32::MIN, f32::max),
+                ]
+            })
+            .collect()
+    } else {
+        rect(annotation).into_iter().collect()
+    }
+}
+
+fn rect(annotation: &Dictionary) -> Option<[f32; 4]> {
+    let r: Vec<f32> = annotation
+        .get(b"Rect")
+        .and_then(Object::as_array)
+        .ok()?
+        .iter()
+        .filter_map(|o| o.as_float().ok())
+        .collect();
+
+    (r.len() == 4).then(|| {
+        [
+            r[0].min(r[2]),
+            r[1].min(r[3]),
+            r[0].max(r[2]),
+            r[1].max(r[3]),
+        ]
+    })
+}
+
+fn top_left(annotation: &Dictionary) -> (f32, f32) {
+    rect(annotation).map_or((0.0, 0.0), |r| (r[0], r[3]))
+}
+
+fn subtype_is(annotation: &Dictionary, subtype: &[u8]) -> bool {
+    annotation
+        .get(b"Subtype")
+        .and_then(Object::as_name)
+        .is_ok_and(|s| s == subtype)
+}
+
+fn page_annotations(doc: &Document, page_id: ObjectId) -> Vec<(ObjectId, &Dictionary)> {
+    let Ok(page) = doc.get_dictionary(page_id) else {
+        return Vec::new();
+    };
+
+    page.get_deref(b"Annots", doc)
+        .and_then(Object::as_array)
+        .map(|annots| {
+            annots
+                .iter()
+                .filter_map(|a| a.as_reference().ok())
+                .filter_map(|id| doc.get_dictionary(id).ok().map(|d| (id, d)))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn annotation_string(annotation: &Dictionary, key: &[u8]) -> Option<String> {
+    annotation
+        .get(key)
+        .and_then(decode_text_string)
+        .ok()
+        .map(|s| s.split_whitespace().collect::<Vec<&str>>().join(" "))
+        .filter(|s| !s.is_empty())
+}
+
+fn info_string(doc: &Document, key: &[u8]) -> Option<String> {
+    doc.trailer
+        .get_deref(b"Info", doc)
+        .and_then(Object::as_dict)
+        .ok()
+        .and_then(|info| info.get_deref(key, doc).ok())
+        .and_then(|o| decode_text_string(o).ok())
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+}
+
+/// PDF dates look like `D:20230820211359+01'00'`, the offset is ignored
+fn annotation_date(annotation: &Dictionary) -> Option<NaiveDateTime> {
+    let date = annotation_string(annotation, b"CreationDate")
+        .or_else(|| annotation_string(annotation, b"M"))?;
+    let digits: String = date
+        .trim_start_matches("D:")
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+
+    NaiveDateTime::parse_from_str(digits.get(..14)?, "%Y%m%d%H%M%S").ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+
+    use lopdf::content::{Content, Operation};
+    use lopdf::{dictionary, Document, Object, Stream, StringFormat};
+
+    use crate::model::HighlightLocation;
+    use crate::pdf::{page_number, parse_pdf};
+
+    fn string(s: &str) -> Object {
+        Object::String(s.as_bytes().to_vec(), StringFormat::Literal)
+    }
+
+    /// a one page PDF with a highlight, which stores a comment as its contents if `contents` is set,
+    /// over text in a font that gives its character widths if `widths` is set
+    fn create_pdf(name: &str, widths: bool, contents: bool) -> std::path::PathBuf {
+        let mut doc = Document::with_version("1.5");
+        let pages_id = doc.new_object_id();
+        let mut font = dictionary! {
+            "Type" => "Font",
+            "Subtype" => "Type1",
+            "BaseFont" => "Courier",
+            "Encoding" => "WinAnsiEncoding",
+            "FirstChar" => 32,
+            "Widths" => vec![Object::Integer(600); 95],
+        };
+        if !widths {
+            font.remove(b"Widths");
+        }
+        let font_id = doc.add_object(font);
+        let resources_id = doc.add_object(dictionary! {
+            "Font" => dictionary! { "F1" => font_id },
+        });
+
+        // each character is 6 points wide at 10pt
+        let content = Content {
+            operations: vec![
+                Operation::new("BT", vec![]),
+                Operation::new("Tf", vec!["F1".into(), 10.into()]),
+                Operation::new("Td", vec![100.into(), 700.into()]),
+                Operation::new("Tj", vec![string("Skip this. Keep this part")]),
+                Operation::new("TL", vec![12.into()]),
+                Operation::new("T*", vec![]),
+                Operation::new("Tj", vec![string("and this line. Not this.")]),
+                Operation::new("ET", vec![]),
+            ],
+        };
+        let content_id = doc.add_object(Stream::new(dictionary! {}, content.encode().unwrap()));
+
+        let mut highlight = dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Highlight",
+            "Rect" => vec![160.into(), 686.into(), 250.into(), 710.into()],
+            "QuadPoints" => vec![
+                160.into(), 710.into(), 250.into(), 710.into(),
+                160.into(), 698.into(), 250.into(), 698.into(),
+                100.into(), 698.into(), 184.into(), 698.into(),
+                100.into(), 686.into(), 184.into(), 686.into(),
+            ],
+            "Contents" => string("Worth remembering"),
+            "M" => string("D:20230820211359+01'00'"),
+        };
+        if !contents {
+            highlight.remove(b"Contents");
+        }
+        let highlight_id = doc.add_object(highlight);
+        let reply_id = doc.add_object(dictionary! {
+            "Type" => "Annot",
+            "Subtype" => "Text",
+            "Rect" => vec![300.into(), 700.into(), 320.into(), 720.into()],
+            "Contents" => string("and a reply"),
+            "IRT" => highlight_id,
+        });
+
+        let page_id = doc.add_object(dictionary! {
+            "Type" => "Page",
+            "Parent" => pages_id,
+            "Contents" => content_id,
+            "Annots" => vec![highlight_id.into(), reply_id.into()],
+        });
+        doc.objects.insert(
+            pages_id,
+            Object::Dictionary(dictionary! {
+                "Type" => "Pages",
+                "Kids" => vec![page_id.into()],
+                "Count" => 1,
+                "Resources" => resources_id,
+                "MediaBox" => vec![0.into(), 0.into(), 595.into(), 842.into()],
+            }),
+        );
+        let catalog_id = doc.add_object(dictionary! {
+            "Type" => "Catalog",
+            "Pages" => pages_id,
+            "PageLabels" => dictionary! {
+                "Nums" => vec![0.into(), dictionary! { "S" => "D", "St" => 12 }.into()],
+            },
+        });
+        let info_id = doc.add_object(dictionary! {
+            "Title" => string("A Paper"),
+            "Author" => string("Doe, Jane"),
+        });
+        doc.trailer.set("Root", catalog_id);
+        doc.trailer.set("Info", info_id);
+
+        let path = env::temp_dir().join(format!("ktr-{}-{}.pdf", name, std::process::id()));
+        doc.save(&path).unwrap();
+        path
+    }
+
+    #[test]
+    fn model() {
+        let path = create_pdf("pdf", true, true);
+        let (book, diagnostics) = parse_pdf(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(diagnostics.is_empty());
+        assert_eq!("A Paper", book.title());
+        assert_eq!("Doe, Jane", book.author());
+        assert_eq!(1, book.highlights().len());
+
+        let hl = book
+            .highlights()
+            .get(&HighlightLocation::new(19_289_160, 19_289_160))
+            .unwrap();
+        assert_eq!(&12, hl.page());
+        assert_eq!("Keep this part and this line.", hl.quote());
+        assert_eq!(
+            "Worth remembering and a reply",
+            hl.note().clone().unwrap().content()
+        );
+        assert_eq!(
+            "2023-08-20 21:13:59",
+            hl.date().unwrap().format("%Y-%m-%d %H:%M:%S").to_string()
+        );
+    }
+
+    #[test]
+    fn unknown_widths() {
+        let path = create_pdf("pdf-widths", false, true);
+        let (book, diagnostics) = parse_pdf(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // the text the annotation stores is used rather than guessing which characters are under it
+        let hl = book.highlights().values().next().unwrap();
+        assert!(diagnostics.is_empty());
+        assert_eq!("Worth remembering", hl.quote());
+        assert_eq!("and a reply", hl.note().clone().unwrap().content());
+
+        let path = create_pdf("pdf-no-text", false, false);
+        let (book, diagnostics) = parse_pdf(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // the reply to the skipped highlight is reported too
+        assert!(book.highlights().is_empty());
+        assert_eq!(2, diagnostics.len());
+        assert!(diagnostics[0]
+            .to_string()
+            .contains("doesn't give its character widths"));
+        assert!(diagnostics[1]
+            .to_string()
+            .contains("has no highlight to attach to"));
+    }
+
+    #[test]
+    fn page_labels() {
+        // i, ii, iii, then 1, 2, ... and an appendix labelled only with a prefix
+        let labels = vec![(0, Some(1)), (3, Some(1)), (10, None)];
+
+        assert_eq!(2, page_number(&labels, 2));
+        assert_eq!(1, page_number(&labels, 4));
+        assert_eq!(7, page_number(&labels, 10));
+        assert_eq!(11, page_number(&labels, 11));
+        assert_eq!(5, page_number(&[], 5));
+    }
+}
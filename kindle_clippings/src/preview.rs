@@ -0,0 +1,160 @@
+//! Dry runs, showing what [render_output](crate::output::render_output) or
+//! [sync_output](crate::sync::sync_output) would do to the output directory without touching it.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use similar::TextDiff;
+
+use crate::model::Book;
+use crate::output::{Format, RenderError};
+use crate::renderer::Renderer;
+use crate::sync::merge_book;
+
+/// What would happen to a note.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    /// the note doesn't exist yet
+    Create,
+    /// the note would be written exactly as it is
+    Unchanged,
+    /// the note would change, as described by the unified diff
+    Modify(String),
+}
+
+/// A note in the output directory and what would happen to it.
+#[derive(Debug, Clone)]
+pub struct Preview {
+    pub file_path: PathBuf,
+    pub change: Change,
+}
+
+impl std::fmt::Display for Preview {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let file = self.file_path.display();
+        match &self.change {
+            Change::Create => write!(f, "Would create {}", file),
+            Change::Unchanged => write!(f, "{} is unchanged", file),
+            Change::Modify(_) => write!(f, "Would modify {}", file),
+        }
+    }
+}
+
+/// Renders the [Book] into memory and compares it with the note already in the output [Path].
+///
+/// When `sync` is set the comparison is with what [sync_output](crate::sync::sync_output) would
+/// write, otherwise with what [render_output](crate::output::render_output) would.
+pub fn preview_output(
+    book: &Book,
+    template: &Option<PathBuf>,
+    format: Format,
+    file_name: &str,
+    output_dir: &Path,
+    sync: bool,
+) -> Result<Preview, RenderError> {
+    Renderer::new(template, format, file_name)?.preview(book, output_dir, sync)
+}
+
+/// compare the already `rendered` [Book] with the note at `file_path`
+pub(crate) fn preview_book(
+    book: &Book,
+    file_path: PathBuf,
+    rendered: String,
+    sync: bool,
+) -> Result<Preview, RenderError> {
+    let existing = match fs::read_to_string(&file_path) {
+        Ok(e) => e,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Ok(Preview {
+                file_path,
+                change: Change::Create,
+            })
+        }
+        Err(e) => return Err(RenderError::ReadExistingFailed(e.to_string())),
+    };
+
+    let new = match sync {
+        true => merge_book(book, &existing, &rendered).map_or(existing.clone(), |(m, _)| m),
+        false => rendered,
+    };
+
+    let change = match existing == new {
+        true => Change::Unchanged,
+        false => Change::Modify(diff(&file_path, &existing, &new)),
+    };
+
+    Ok(Preview { file_path, change })
+}
+
+/// a unified diff from `old` to `new`
+fn diff(file_path: &Path, old: &str, new: &str) -> String {
+    let name = file_path.display().to_string();
+
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .context_radius(2)
+        .header(&name, &name)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use crate::filename::DEFAULT_FILE_NAME;
+    use crate::model::{Book, Highlight, HighlightLocation};
+    use crate::output::{render_output, Format};
+    use crate::preview::{preview_output, Change};
+
+    #[test]
+    fn changes() {
+        let mut book = Book::new("A Book".to_string(), "Doe, Jane".to_string());
+        book.add_highlight(Highlight::new(
+            1,
+            HighlightLocation::new(10, 10),
+            "First".to_string(),
+        ));
+
+        let dir = env::temp_dir().join(format!("ktr-preview-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let preview = |book: &Book, sync| {
+            preview_output(book, &None, Format::Logseq, DEFAULT_FILE_NAME, &dir, sync).unwrap()
+        };
+
+        let created = preview(&book, false);
+        assert_eq!(Change::Create, created.change);
+        assert!(!created.file_path.exists());
+
+        render_output(&book, &None, Format::Logseq, DEFAULT_FILE_NAME, &dir).unwrap();
+        assert_eq!(Change::Unchanged, preview(&book, false).change);
+
+        book.add_highlight(Highlight::new(
+            2,
+            HighlightLocation::new(20, 20),
+            "Second".to_string(),
+        ));
+        let modified = preview(&book, false);
+
+        // a sync keeps edits made to the page, only adding the new highlight
+        let page = created.file_path;
+        let edited = fs::read_to_string(&page)
+            .unwrap()
+            .replace("- First\n", "- First\n\t- My own thought\n");
+        fs::write(&page, edited).unwrap();
+        let synced = preview(&book, true);
+        fs::remove_dir_all(&dir).unwrap();
+
+        let Change::Modify(diff) = modified.change else {
+            panic!("expected a diff, got {:?}", modified.change);
+        };
+        assert!(diff.starts_with("--- "));
+        assert!(diff.contains("\n+- Second\n"));
+
+        let Change::Modify(diff) = synced.change else {
+            panic!("expected a diff, got {:?}", synced.change);
+        };
+        assert!(diff.contains("\n+- Second\n"));
+        assert!(!diff.contains("-\t- My own thought"));
+    }
+}
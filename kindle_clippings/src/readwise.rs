@@ -0,0 +1,270 @@
+use std::collections::HashMap;
+use std::io::Read;
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDateTime};
+use serde::Deserialize;
+
+use crate::model::{Book, Highlight, HighlightLocation, Note};
+use crate::source::{has_extension, ClippingSource, Diagnostic, Import, SourceError};
+
+/// A CSV export from Readwise.
+pub struct Readwise;
+
+impl ClippingSource for Readwise {
+    fn name(&self) -> &'static str {
+        "readwise"
+    }
+
+    fn accepts(&self, path: &Path) -> bool {
+        has_extension(path, &["csv"])
+    }
+
+    fn import(&self, path: &Path) -> Result<Import, SourceError> {
+        match std::fs::File::open(path) {
+            Ok(f) => parse_readwise_csv(f),
+            Err(e) => Err(SourceError::ReadFailed(e.to_string())),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Row {
+    #[serde(rename = "Highlight")]
+    highlight: String,
+    #[serde(rename = "Book Title")]
+    title: String,
+    #[serde(rename = "Book Author", default)]
+    author: String,
+    #[serde(rename = "Note", default)]
+    note: String,
+    #[serde(rename = "Tags", default)]
+    tags: String,
+    #[serde(rename = "Location Type", default)]
+    location_type: String,
+    #[serde(rename = "Location", default)]
+    location: String,
+    #[serde(rename = "Highlighted at", default)]
+    highlighted_at: String,
+}
+
+/// read a Readwise CSV export into a library
+///
+/// Readwise locations are only treated as page numbers when the export's `Location Type` says
+/// so, otherwise the page is left as `0`. Highlights without a location are kept at location `0`.
+/// Each highlight after the first at a location, such as several on one page, runs from the
+/// location to the location plus its position among them, in the order they appear in the export.
+pub fn parse_readwise_csv<R>(input: R) -> Result<Import, SourceError>
+where
+    R: Read,
+{
+    let mut reader = csv::Reader::from_reader(input);
+
+    let headers = reader
+        .headers()
+        .map_err(|e| SourceError::ParsingFailed(e.to_string()))?;
+
+    for column in ["Highlight", "Book Title"] {
+        if !headers.iter().any(|h| h == column) {
+            return Err(SourceError::ParsingFailed(format!(
+                "missing '{}' column, is this a Readwise export?",
+                column
+            )));
+        }
+    }
+
+    let mut library: HashMap<String, Book> = HashMap::new();
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+
+    for (i, row) in reader.deserialize::<Row>().enumerate() {
+        let row = match row {
+            Ok(r) => r,
+            Err(e) => {
+                diagnostics.push(Diagnostic::new(Some(i + 1), e.to_string()));
+                continue;
+            }
+        };
+
+        if row.highlight.trim().is_empty() {
+            diagnostics.push(Diagnostic::new(Some(i + 1), "empty highlight".to_string()));
+            continue;
+        }
+
+        let book = library.entry(row.title.clone()).or_insert_with(|| {
+            let mut book = Book::new(row.title.clone(), row.author.clone());
+            book.set_source("readwise");
+            book
+        });
+
+        let location = row.location.trim().parse::<u64>().unwrap_or(0);
+
+        let page = if row.location_type.eq_ignore_ascii_case("page") {
+            location
+        } else {
+            0
+        };
+
+        // highlights sharing a location, like those on the same page, are told apart by their
+        // position among the others there
+        let quote = row.highlight.trim();
+        let mut location_key = HighlightLocation::new(location, location);
+        let mut duplicate = false;
+        while let Some(existing) = book.highlights().get(&location_key) {
+            if existing.quote() == quote {
+                duplicate = true;
+                break;
+            }
+            location_key =
+                HighlightLocation::with_ordinal(location, location, location_key.ordinal() + 1);
+        }
+
+        if duplicate {
+            diagnostics.push(Diagnostic::new(
+                Some(i + 1),
+                format!(
+                    "'{}' already has this highlight at location {}",
+                    row.title, location
+                ),
+            ));
+            continue;
+        }
+
+        let mut highlight = Highlight::new(page, location_key, quote.to_string());
+
+        if let Some(date) = parse_date(&row.highlighted_at) {
+            highlight.add_date(date);
+        }
+
+        row.tags
+            .split(',')
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .for_each(|t| highlight.add_tag(t.to_string()));
+
+        // the note belongs to this row, not whichever highlights are near it
+        if !row.note.trim().is_empty() {
+            highlight.add_note(Note::new(page, location, row.note.trim().to_string()));
+        }
+
+        book.add_highlight(highlight);
+    }
+
+    Ok(Import {
+        library,
+        diagnostics,
+    })
+}
+
+/// Readwise writes `2023-08-20 21:13:59+00:00`, older exports leave the offset off
+fn parse_date(date: &str) -> Option<NaiveDateTime> {
+    let date = date.trim();
+
+    DateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S%:z")
+        .map(|d| d.naive_utc())
+        .or_else(|_| NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M:%S"))
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::model::HighlightLocation;
+    use crate::readwise::parse_readwise_csv;
+
+    const INPUT: &str = "\
+Highlight,Book Title,Book Author,Amazon Book ID,Note,Color,Tags,Location Type,Location,Highlighted at
+\"For most people the truth is that it’s all about the path of least resistance.\",The 5 AM Club,Robin Sharma,B07B8L24R3,,yellow,\"habits,effort\",location,1370,2023-08-20 21:13:59+01:00
+\"Gamble everything for love,
+if you are a true human being.\",The 5 AM Club,Robin Sharma,B07B8L24R3,Smidgen the pigeon,yellow,,location,1460,2023-08-21 22:01:40+00:00
+,The 5 AM Club,Robin Sharma,B07B8L24R3,,yellow,,location,1500,
+";
+
+    #[test]
+    fn model() {
+        let import = parse_readwise_csv(INPUT.as_bytes()).unwrap();
+        assert_eq!(1, import.library.len());
+        assert_eq!(1, import.diagnostics.len());
+
+        let book = import.library.get("The 5 AM Club").unwrap();
+        assert_eq!("Robin Sharma", book.author());
+        assert_eq!(2, book.highlights().len());
+
+        let first = book
+            .highlights()
+            .get(&HighlightLocation::new(1370, 1370))
+            .unwrap();
+        assert_eq!(&["habits".to_string(), "effort".to_string()], first.tags());
+        assert_eq!(
+            "2023-08-20 20:13:59",
+            first
+                .date()
+                .unwrap()
+                .format("%Y-%m-%d %H:%M:%S")
+                .to_string()
+        );
+
+        let second = book
+            .highlights()
+            .get(&HighlightLocation::new(1460, 1460))
+            .unwrap();
+        assert_eq!(
+            "Smidgen the pigeon",
+            second.note().clone().unwrap().content()
+        );
+    }
+
+    #[test]
+    fn shared_locations() {
+        let input = "\
+Highlight,Book Title,Book Author,Note,Location Type,Location
+First on the page,A Paper,Jane Doe,,page,12
+Second on the page,A Paper,Jane Doe,A note,page,12
+Second on the page,A Paper,Jane Doe,,page,12
+Third on the page,A Paper,Jane Doe,,page,12
+Without a location,A Paper,Jane Doe,,,
+Also without,A Paper,Jane Doe,,,
+First at 1370,A Paper,Jane Doe,,location,1370
+Second at 1370,A Paper,Jane Doe,,location,1370
+Third at 1370,A Paper,Jane Doe,,location,1370
+At 1371,A Paper,Jane Doe,Next door,location,1371
+";
+        let import = parse_readwise_csv(input.as_bytes()).unwrap();
+        let book = import.library.get("A Paper").unwrap();
+        let quote = |location: u64, ordinal: u32| {
+            let hl = book
+                .highlights()
+                .get(&HighlightLocation::with_ordinal(
+                    location, location, ordinal,
+                ))
+                .unwrap();
+            (
+                hl.quote(),
+                *hl.page(),
+                hl.note().as_ref().map(|n| n.content()),
+            )
+        };
+
+        assert_eq!(9, book.highlights().len());
+        assert_eq!(("First on the page", 12, None), quote(12, 0));
+        assert_eq!(("Second on the page", 12, Some("A note")), quote(12, 1));
+        assert_eq!(("Third on the page", 12, None), quote(12, 2));
+        assert_eq!(("Without a location", 0, None), quote(0, 0));
+        assert_eq!(("Also without", 0, None), quote(0, 1));
+
+        // highlights sharing a location don't spill into the next one, so its note stays put
+        assert_eq!(("First at 1370", 0, None), quote(1370, 0));
+        assert_eq!(("Second at 1370", 0, None), quote(1370, 1));
+        assert_eq!(("Third at 1370", 0, None), quote(1370, 2));
+        assert_eq!(("At 1371", 0, Some("Next door")), quote(1371, 0));
+
+        // only the repeated row is reported
+        assert_eq!(1, import.diagnostics.len());
+        assert!(import.diagnostics[0]
+            .to_string()
+            .contains("already has this highlight"));
+    }
+
+    #[test]
+    fn not_readwise() {
+        assert!(parse_readwise_csv("Title,Author\nA,B\n".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,396 @@
+//! Rendering many books with the same templates, compiled once rather than for every book.
+
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+use tera::Tera;
+
+use crate::filename::FileNameTemplate;
+use crate::model::Book;
+use crate::output::{
+    book_context, create_parent_dir, load_templates, tera_error, write_atomically, Format,
+    RenderError,
+};
+use crate::preview::{preview_book, Preview};
+use crate::rules::Rules;
+use crate::sync::{sync_book, SyncSummary};
+
+/// A template, or template directory, and file name template compiled for a [Format], ready to
+/// render any number of books.
+///
+/// The free functions like [render_output](crate::output::render_output) build a renderer for
+/// each book, use one directly to render a whole library.
+pub struct Renderer {
+    tera: Tera,
+    template_name: String,
+    format: Format,
+    file_name: FileNameTemplate,
+    backup: bool,
+}
+
+impl Renderer {
+    /// compile the user's template, or the default for the [Format], and the `file_name` template,
+    /// see [filename](crate::filename)
+    pub fn new(
+        template: &Option<PathBuf>,
+        format: Format,
+        file_name: &str,
+    ) -> Result<Renderer, RenderError> {
+        let (tera, template_name) = load_templates(template, format.template_name())?;
+
+        Ok(Renderer {
+            tera,
+            template_name,
+            format,
+            file_name: FileNameTemplate::new(file_name)?,
+            backup: false,
+        })
+    }
+
+    /// whether to keep the previous version of each note [write](Renderer::write) or
+    /// [sync](Renderer::sync) replaces, next to it with `.bak` on the end of its name
+    pub fn keep_backups(&mut self, backup: bool) {
+        self.backup = backup;
+    }
+
+    /// render the [Book] to a [String]
+    pub fn render(&self, book: &Book) -> Result<String, RenderError> {
+        self.tera
+            .render(&self.template_name, &book_context(book))
+            .map_err(|e| RenderError::ParsingFailed(tera_error(&e)))
+    }
+
+    /// render the [Book] to any [Write]r
+    pub fn render_to<W: Write>(&self, book: &Book, writer: W) -> Result<(), RenderError> {
+        self.tera
+            .render_to(&self.template_name, &book_context(book), writer)
+            .map_err(|e| RenderError::ParsingFailed(tera_error(&e)))
+    }
+
+    /// where [Renderer::write] writes the [Book] in the output [Path]
+    pub fn output_path(&self, book: &Book, output_dir: &Path) -> Result<PathBuf, RenderError> {
+        Ok(output_dir.join(self.file_name.path(book, self.format.extension())?))
+    }
+
+    /// render the [Book] to its file in the output [Path], returning where it was written
+    ///
+    /// The note is only replaced once the whole book has rendered, so a failing template leaves
+    /// any note already there as it was.
+    pub fn write(&self, book: &Book, output_dir: &Path) -> Result<PathBuf, RenderError> {
+        self.write_to_path(book, self.output_path(book, output_dir)?)
+    }
+
+    fn write_to_path(&self, book: &Book, file_path: PathBuf) -> Result<PathBuf, RenderError> {
+        create_parent_dir(&file_path)?;
+
+        write_atomically(&file_path, self.backup, |writer| {
+            self.render_to(book, writer)
+        })?;
+
+        Ok(file_path)
+    }
+
+    /// [write](Renderer::write) every book, spread across the machine's cores, with what happened
+    /// to each in the order they were given
+    ///
+    /// Books whose file names would put them in the same note aren't written at all, rather than
+    /// one silently replacing the others.
+    pub fn write_all<'a>(
+        &self,
+        books: &[&'a Book],
+        output_dir: &Path,
+    ) -> Vec<(&'a Book, Result<PathBuf, RenderError>)> {
+        let paths: Vec<Result<PathBuf, RenderError>> = books
+            .iter()
+            .map(|book| self.output_path(book, output_dir))
+            .collect();
+
+        let mut titles: HashMap<PathBuf, Vec<&str>> = HashMap::new();
+        for (book, path) in books.iter().zip(paths.iter()) {
+            if let Ok(path) = path {
+                titles.entry(path.clone()).or_default().push(book.title());
+            }
+        }
+
+        books
+            .par_iter()
+            .zip(paths.into_par_iter())
+            .map(|(book, path)| {
+                let result = path.and_then(|path| match &titles[&path] {
+                    shared if shared.len() > 1 => {
+                        Err(RenderError::CreateOutputFileFailed(format!(
+                            "{} would be the note for {}, give them different file names",
+                            path.display(),
+                            shared.join(", ")
+                        )))
+                    }
+                    _ => self.write_to_path(book, path),
+                });
+                (*book, result)
+            })
+            .collect()
+    }
+
+    /// add the highlights the [Book]'s existing note doesn't have, see [sync](crate::sync)
+    pub fn sync(&self, book: &Book, output_dir: &Path) -> Result<SyncSummary, RenderError> {
+        sync_book(
+            book,
+            self.output_path(book, output_dir)?,
+            &self.render(book)?,
+            self.backup,
+        )
+    }
+
+    /// what [write](Renderer::write), or [sync](Renderer::sync) if `sync` is set, would do to the
+    /// [Book]'s note, see [preview](crate::preview)
+    pub fn preview(
+        &self,
+        book: &Book,
+        output_dir: &Path,
+        sync: bool,
+    ) -> Result<Preview, RenderError> {
+        preview_book(
+            book,
+            self.output_path(book, output_dir)?,
+            self.render(book)?,
+            sync,
+        )
+    }
+}
+
+/// A [Renderer] for every template [Rules] can choose, so each template is compiled once for a
+/// whole run however many books and folders it's used for.
+pub struct Renderers<'a> {
+    rules: &'a Rules,
+    template: &'a Option<PathBuf>,
+    renderers: Vec<(&'a Option<PathBuf>, Renderer)>,
+}
+
+impl<'a> Renderers<'a> {
+    /// compile `template` and the templates of the `rules`, see [Renderer::new]
+    pub fn new(
+        rules: &'a Rules,
+        template: &'a Option<PathBuf>,
+        format: Format,
+        file_name: &str,
+    ) -> Result<Renderers<'a>, RenderError> {
+        let renderers = rules
+            .templates(template)
+            .into_iter()
+            .map(|t| Ok((t, Renderer::new(t, format, file_name)?)))
+            .collect::<Result<_, RenderError>>()?;
+
+        Ok(Renderers {
+            rules,
+            template,
+            renderers,
+        })
+    }
+
+    /// see [Renderer::keep_backups]
+    pub fn keep_backups(&mut self, backup: bool) {
+        for (_, renderer) in self.renderers.iter_mut() {
+            renderer.keep_backups(backup);
+        }
+    }
+
+    /// the books [grouped](Rules::group) by the template and folder chosen for them, with the
+    /// [Renderer] for each group's template
+    pub fn group<'b>(
+        &self,
+        books: impl IntoIterator<Item = &'b Book>,
+        output_dir: &Path,
+    ) -> Vec<(&Renderer, PathBuf, Vec<&'b Book>)> {
+        self.rules
+            .group(books, self.template, output_dir)
+            .into_iter()
+            .map(|(choice, books)| {
+                // every template the rules can choose was compiled in new
+                let (_, renderer) = self
+                    .renderers
+                    .iter()
+                    .find(|(t, _)| *t == choice.template)
+                    .unwrap();
+                (renderer, choice.output_dir, books)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use crate::filename::DEFAULT_FILE_NAME;
+    use crate::model::{Book, Highlight, HighlightLocation};
+    use crate::output::{render_book, Format};
+    use crate::renderer::{Renderer, Renderers};
+    use crate::rules::Rules;
+
+    #[test]
+    fn write_all() {
+        let books: Vec<Book> = (1..=40)
+            .map(|i| {
+                let mut book = Book::new(format!("Book {}", i), "Doe, Jane".to_string());
+                book.add_highlight(Highlight::new(
+                    i,
+                    HighlightLocation::new(i * 10, i * 10),
+                    format!("Quote {}", i),
+                ));
+                book
+            })
+            .collect();
+        let refs: Vec<&Book> = books.iter().collect();
+
+        let dir = env::temp_dir().join(format!("ktr-renderer-{}", std::process::id()));
+        let renderer = Renderer::new(&None, Format::Org, "{{ author }}/{{ title }}").unwrap();
+        let results = renderer.write_all(&refs, &dir);
+
+        let written: Vec<(String, String)> = results
+            .into_iter()
+            .map(|(book, result)| {
+                let path = result.unwrap();
+                (book.title().to_string(), fs::read_to_string(path).unwrap())
+            })
+            .collect();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(40, written.len());
+        for ((title, note), book) in written.iter().zip(books.iter()) {
+            assert_eq!(book.title(), title);
+            assert_eq!(&render_book(book, &None, Format::Org).unwrap(), note);
+        }
+    }
+
+    #[test]
+    fn shared_path() {
+        let books = [
+            Book::new("First".to_string(), "Doe, Jane".to_string()),
+            Book::new("Second".to_string(), "Doe, Jane".to_string()),
+            Book::new("Third".to_string(), "Roe, Richard".to_string()),
+        ];
+        let refs: Vec<&Book> = books.iter().collect();
+
+        let dir = env::temp_dir().join(format!("ktr-renderer-shared-{}", std::process::id()));
+        let renderer = Renderer::new(&None, Format::Markdown, "{{ author }}").unwrap();
+        let results = renderer.write_all(&refs, &dir);
+        let mut files: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        fs::remove_dir_all(&dir).unwrap();
+
+        // neither book that shares a note gets to overwrite the other
+        for (_, result) in &results[..2] {
+            assert_eq!(
+                format!(
+                    "{} would be the note for First, Second, give them different file names",
+                    dir.join("Doe, Jane.md").display()
+                ),
+                result.as_ref().unwrap_err().to_string()
+            );
+        }
+        assert!(results[2].1.is_ok());
+        assert_eq!(vec!["Roe, Richard.md"], files);
+    }
+
+    #[test]
+    fn safe_writes() {
+        let book = Book::new("A Book".to_string(), "Doe, Jane".to_string());
+        let dir = env::temp_dir().join(format!("ktr-renderer-safe-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let broken = dir.join("broken.md");
+        fs::write(&broken, "# {{ title }}\n{{ missing }}\n").unwrap();
+
+        let mut renderer = Renderer::new(&None, Format::Markdown, "{{ title }}").unwrap();
+        renderer.keep_backups(true);
+        let note = dir.join("A Book.md");
+        fs::write(&note, "Edited by hand").unwrap();
+
+        // a template that fails part way through leaves the note alone
+        let failing = Renderer::new(&Some(broken), Format::Markdown, "{{ title }}").unwrap();
+        assert!(failing.write(&book, &dir).is_err());
+        assert_eq!("Edited by hand", fs::read_to_string(&note).unwrap());
+
+        renderer.write(&book, &dir).unwrap();
+        let written = fs::read_to_string(&note).unwrap();
+        let backup = fs::read_to_string(dir.join("A Book.md.bak")).unwrap();
+        let mut files: Vec<String> = fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        files.sort();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(
+            render_book(&book, &None, Format::Markdown).unwrap(),
+            written
+        );
+        assert_eq!("Edited by hand", backup);
+        // no temporary files are left behind
+        assert_eq!(vec!["A Book.md", "A Book.md.bak", "broken.md"], files);
+    }
+
+    #[test]
+    fn renderers() {
+        let dir = env::temp_dir().join(format!("ktr-renderers-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("fiction.md"), "Fiction: {{ title }}").unwrap();
+        let rules = Rules::parse(
+            "[[rule]]\nauthor = \"pratchett\"\ntemplate = \"fiction.md\"\nfolder = \"Fiction\"\n\n\
+             [[rule]]\ntitle = \"dune\"\nfolder = \"Science Fiction\"",
+            &dir,
+        )
+        .unwrap();
+        let books = [
+            Book::new("Mort".to_string(), "Pratchett, Terry".to_string()),
+            Book::new("Dune".to_string(), "Herbert, Frank".to_string()),
+            Book::new("Emma".to_string(), "Austen, Jane".to_string()),
+        ];
+
+        let renderers = Renderers::new(&rules, &None, Format::Markdown, DEFAULT_FILE_NAME).unwrap();
+        let groups = renderers.group(books.iter(), Path::new("/notes"));
+        fs::remove_dir_all(&dir).unwrap();
+
+        let rendered: Vec<(String, PathBuf)> = groups
+            .iter()
+            .map(|(renderer, dir, books)| (renderer.render(books[0]).unwrap(), dir.clone()))
+            .collect();
+
+        assert_eq!(3, rendered.len());
+        assert_eq!(
+            ("Fiction: Mort".to_string(), PathBuf::from("/notes/Fiction")),
+            rendered[0]
+        );
+        // the default template is shared by the books the second rule and no rule chose
+        assert_eq!(PathBuf::from("/notes/Science Fiction"), rendered[1].1);
+        assert_eq!(
+            render_book(&books[2], &None, Format::Markdown).unwrap(),
+            rendered[2].0
+        );
+        assert!(Renderers::new(
+            &rules,
+            &Some(dir.join("missing.md")),
+            Format::Markdown,
+            DEFAULT_FILE_NAME
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn paths() {
+        let book = Book::new("A Book".to_string(), "Doe, Jane".to_string());
+        let renderer = Renderer::new(&None, Format::Logseq, DEFAULT_FILE_NAME).unwrap();
+
+        assert_eq!(
+            env::temp_dir().join("Doe, Jane. A Book.md"),
+            renderer.output_path(&book, &env::temp_dir()).unwrap()
+        );
+        assert!(Renderer::new(&None, Format::Logseq, "{{ title").is_err());
+    }
+}
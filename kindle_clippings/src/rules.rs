@@ -0,0 +1,329 @@
+//! Rules for picking a template and output folder for each book, so technical books, fiction and
+//! papers can each have their own layout.
+//!
+//! Rules are kept in a TOML file as a list of `[[rule]]` tables, checked in order with the first
+//! that matches a book winning:
+//!
+//! ```toml
+//! [[rule]]
+//! author = "Pratchett"
+//! template = "templates/fiction.md"
+//! folder = "Fiction"
+//!
+//! [[rule]]
+//! title_regex = "(?i)^(the )?art of"
+//! template = "templates/technical"
+//! folder = "Technical/Craft"
+//! ```
+//!
+//! `title` and `author` match when the book's title or author contains them, ignoring case, and
+//! `title_regex` and `author_regex` when the [regular expression](https://docs.rs/regex) matches.
+//! A rule with more than one of these needs all of them to match, and a rule with none matches
+//! every book. `template` is a template file or directory, relative to the rules file, and `folder`
+//! is a subfolder of the output directory, which can contain `/`. Either can be left out to keep
+//! the template or output directory that would otherwise be used.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+use toml_edit::{DocumentMut, Item};
+
+use crate::filename::{is_named_segment, sanitise};
+use crate::model::Book;
+
+/// The rules read from a rules file, see the [module documentation](self).
+#[derive(Debug, Clone, Default)]
+pub struct Rules {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Rule {
+    title: Option<String>,
+    author: Option<String>,
+    title_regex: Option<Regex>,
+    author_regex: Option<Regex>,
+    template: Option<PathBuf>,
+    folder: Option<PathBuf>,
+}
+
+/// The template and output directory [Rules] chose for a book.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Choice<'a> {
+    pub template: &'a Option<PathBuf>,
+    pub output_dir: PathBuf,
+}
+
+impl Rules {
+    /// read the rules from a TOML file
+    pub fn load(path: &Path) -> Result<Rules, RulesError> {
+        let rules = fs::read_to_string(path).map_err(|e| {
+            RulesError::ReadFailed(format!("Unable to read {}: {}", path.display(), e))
+        })?;
+
+        Rules::parse(&rules, path.parent().unwrap_or(Path::new("")))
+    }
+
+    /// read the rules from TOML, with templates relative to `base_dir`
+    pub fn parse(rules: &str, base_dir: &Path) -> Result<Rules, RulesError> {
+        let doc: DocumentMut = rules
+            .parse()
+            .map_err(|e: toml_edit::TomlError| RulesError::Invalid(e.to_string()))?;
+
+        for (key, _) in doc.iter() {
+            if key != "rule" {
+                return Err(RulesError::Invalid(format!(
+                    "unknown key `{}`, rules are [[rule]] tables",
+                    key
+                )));
+            }
+        }
+
+        let tables = match doc.get("rule") {
+            Some(item) => item
+                .as_array_of_tables()
+                .ok_or_else(|| RulesError::Invalid("rules must be [[rule]] tables".to_string()))?,
+            None => return Ok(Rules::default()),
+        };
+
+        let mut parsed: Vec<Rule> = Vec::new();
+        for (i, table) in tables.iter().enumerate() {
+            let invalid = |msg: String| RulesError::Invalid(format!("rule {}: {}", i + 1, msg));
+            let mut rule = Rule::default();
+
+            for (key, item) in table.iter() {
+                let value =
+                    string(item).ok_or_else(|| invalid(format!("`{}` must be text", key)))?;
+                let regex = || Regex::new(value).map_err(|e| invalid(e.to_string()));
+
+                match key {
+                    "title" => rule.title = Some(value.to_lowercase()),
+                    "author" => rule.author = Some(value.to_lowercase()),
+                    "title_regex" => rule.title_regex = Some(regex()?),
+                    "author_regex" => rule.author_regex = Some(regex()?),
+                    "template" => rule.template = Some(base_dir.join(value)),
+                    "folder" => rule.folder = Some(folder(value)),
+                    _ => return Err(invalid(format!("unknown key `{}`", key))),
+                }
+            }
+
+            parsed.push(rule);
+        }
+
+        Ok(Rules { rules: parsed })
+    }
+
+    /// the template and output directory for the book, from the first rule it matches, otherwise
+    /// the `template` and `output_dir` given
+    pub fn choose<'a>(
+        &'a self,
+        book: &Book,
+        template: &'a Option<PathBuf>,
+        output_dir: &Path,
+    ) -> Choice<'a> {
+        match self.rules.iter().find(|r| r.matches(book)) {
+            Some(rule) => Choice {
+                template: match rule.template {
+                    Some(_) => &rule.template,
+                    None => template,
+                },
+                output_dir: match &rule.folder {
+                    Some(folder) => output_dir.join(folder),
+                    None => output_dir.to_path_buf(),
+                },
+            },
+            None => Choice {
+                template,
+                output_dir: output_dir.to_path_buf(),
+            },
+        }
+    }
+
+    /// every template a book could be given, `template` first and then those of the rules in order,
+    /// each only once
+    pub fn templates<'a>(&'a self, template: &'a Option<PathBuf>) -> Vec<&'a Option<PathBuf>> {
+        let mut templates = vec![template];
+        for rule in self.rules.iter().filter(|r| r.template.is_some()) {
+            if !templates.contains(&&rule.template) {
+                templates.push(&rule.template);
+            }
+        }
+        templates
+    }
+
+    /// the books [choose]n for each template and output directory, in the order each choice was
+    /// first made, so the templates can be compiled once for all of their books
+    ///
+    /// [choose]: Rules::choose
+    pub fn group<'a, 'b>(
+        &'a self,
+        books: impl IntoIterator<Item = &'b Book>,
+        template: &'a Option<PathBuf>,
+        output_dir: &Path,
+    ) -> Vec<(Choice<'a>, Vec<&'b Book>)> {
+        let mut groups: Vec<(Choice, Vec<&Book>)> = Vec::new();
+
+        for book in books {
+            let choice = self.choose(book, template, output_dir);
+            match groups.iter_mut().find(|(c, _)| *c == choice) {
+                Some((_, books)) => books.push(book),
+                None => groups.push((choice, vec![book])),
+            }
+        }
+
+        groups
+    }
+}
+
+impl Rule {
+    fn matches(&self, book: &Book) -> bool {
+        let title = book.title().to_lowercase();
+        let author = book.author().to_lowercase();
+
+        self.title.as_ref().map_or(true, |t| title.contains(t))
+            && self.author.as_ref().map_or(true, |a| author.contains(a))
+            && self
+                .title_regex
+                .as_ref()
+                .map_or(true, |r| r.is_match(book.title()))
+            && self
+                .author_regex
+                .as_ref()
+                .map_or(true, |r| r.is_match(book.author()))
+    }
+}
+
+fn string(item: &Item) -> Option<&str> {
+    item.as_value().and_then(|v| v.as_str())
+}
+
+/// a folder made safe the same way file names are, see [sanitise]
+fn folder(value: &str) -> PathBuf {
+    value
+        .split(['/', '\\'])
+        .filter(|p| is_named_segment(p))
+        .map(sanitise)
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum RulesError {
+    ReadFailed(String),
+    Invalid(String),
+}
+
+impl std::fmt::Display for RulesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RulesError::ReadFailed(s) => write!(f, "{}", s),
+            RulesError::Invalid(s) => write!(f, "Invalid rules: {}", s),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use crate::model::Book;
+    use crate::rules::Rules;
+
+    const RULES: &str = r#"
+[[rule]]
+author = "pratchett"
+template = "fiction.md"
+folder = "Fiction/Discworld"
+
+[[rule]]
+title_regex = "^(The )?Art of"
+author_regex = "Knuth"
+folder = "../Technical"
+
+[[rule]]
+title = "paper"
+template = "papers"
+"#;
+
+    #[test]
+    fn choosing() {
+        let rules = Rules::parse(RULES, Path::new("/rules")).unwrap();
+        let default = Some(PathBuf::from("/default.md"));
+        let output = Path::new("/notes");
+        let choose = |title: &str, author: &str| {
+            let book = Book::new(title.to_string(), author.to_string());
+            let choice = rules.choose(&book, &default, output);
+            (choice.template.clone(), choice.output_dir)
+        };
+
+        assert_eq!(
+            (
+                Some(PathBuf::from("/rules/fiction.md")),
+                PathBuf::from("/notes/Fiction/Discworld")
+            ),
+            choose("Mort", "Pratchett, Terry")
+        );
+        assert_eq!(
+            (default.clone(), PathBuf::from("/notes/Technical")),
+            choose("The Art of Computer Programming", "Knuth, Donald")
+        );
+        // both conditions have to match
+        assert_eq!(
+            (default.clone(), PathBuf::from("/notes")),
+            choose("The Art of War", "Tzu, Sun")
+        );
+        assert_eq!(
+            (
+                Some(PathBuf::from("/rules/papers")),
+                PathBuf::from("/notes")
+            ),
+            choose("A Paper on Papers", "Doe, Jane")
+        );
+    }
+
+    #[test]
+    fn grouping() {
+        let rules = Rules::parse(RULES, Path::new("/rules")).unwrap();
+        let books = [
+            Book::new("Mort".to_string(), "Pratchett, Terry".to_string()),
+            Book::new("Dune".to_string(), "Herbert, Frank".to_string()),
+            Book::new("Sourcery".to_string(), "Pratchett, Terry".to_string()),
+        ];
+
+        let groups = rules.group(books.iter(), &None, Path::new("/notes"));
+        let titles: Vec<Vec<&str>> = groups
+            .iter()
+            .map(|(_, books)| books.iter().map(|b| b.title()).collect())
+            .collect();
+
+        assert_eq!(vec![vec!["Mort", "Sourcery"], vec!["Dune"]], titles);
+        assert_eq!(PathBuf::from("/notes"), groups[1].0.output_dir);
+
+        let default = Some(PathBuf::from("/default.md"));
+        assert_eq!(
+            vec![
+                &default,
+                &Some(PathBuf::from("/rules/fiction.md")),
+                &Some(PathBuf::from("/rules/papers")),
+            ],
+            rules.templates(&default)
+        );
+    }
+
+    #[test]
+    fn invalid() {
+        let parse = |rules: &str| Rules::parse(rules, Path::new("")).unwrap_err().to_string();
+
+        assert_eq!(
+            "Invalid rules: rule 1: unknown key `titel`",
+            parse("[[rule]]\ntitel = \"x\"")
+        );
+        assert_eq!(
+            "Invalid rules: rule 2: `folder` must be text",
+            parse("[[rule]]\n[[rule]]\nfolder = 3")
+        );
+        assert!(parse("[[rule]]\ntitle_regex = \"(\"").starts_with("Invalid rules: rule 1: "));
+        assert!(parse("[rule]\ntitle = \"x\"").contains("[[rule]]"));
+        assert!(Rules::parse("", Path::new("")).is_ok());
+    }
+}
@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::json::JsonLibrary;
+use crate::kobo::Kobo;
+use crate::model::Book;
+use crate::pdf::Pdf;
+use crate::readwise::Readwise;
+use crate::Kindle;
+
+/// Something that can turn an input file into a library of [Book]s.
+///
+/// Frontends shouldn't need to know which devices or exports exist, so they pick an
+/// implementation with [source_for_path] or [source_by_name] and only deal with the [Import].
+pub trait ClippingSource {
+    /// short, lowercase name used to select the source, e.g. `kindle`
+    fn name(&self) -> &'static str;
+
+    /// whether the file looks like something this source can read, judged by its name
+    fn accepts(&self, path: &Path) -> bool;
+
+    /// read the file into a library, along with anything that couldn't be read
+    fn import(&self, path: &Path) -> Result<Import, SourceError>;
+
+    /// whether the highlights' locations are ones the source recorded, rather than numbers made
+    /// up to keep them in order, which readers have no use for
+    fn has_locations(&self) -> bool {
+        true
+    }
+}
+
+/// The outcome of reading a [ClippingSource].
+#[derive(Debug, Clone, Default)]
+pub struct Import {
+    /// a [HashMap<String, Book>] where the [String] represents the book's title
+    pub library: HashMap<String, Book>,
+    /// entries that were skipped or only partly understood
+    pub diagnostics: Vec<Diagnostic>,
+}
+
+/// A problem with a single entry of an input that didn't stop the rest from being read.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// position of the entry within the input, where the source has such a thing
+    pub entry: Option<usize>,
+    pub message: String,
+}
+
+impl Diagnostic {
+    pub fn new(entry: Option<usize>, message: String) -> Self {
+        Diagnostic { entry, message }
+    }
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.entry {
+            Some(e) => write!(f, "entry {}: {}", e, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SourceError {
+    ReadFailed(String),
+    ParsingFailed(String),
+}
+
+impl std::fmt::Display for SourceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceError::ReadFailed(s) => write!(f, "{}", s),
+            SourceError::ParsingFailed(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+/// every [ClippingSource] that ships with this crate
+pub fn sources() -> Vec<Box<dyn ClippingSource>> {
+    vec![
+        Box::new(Kindle),
+        Box::new(Kobo),
+        Box::new(Readwise),
+        Box::new(Pdf),
+        Box::new(JsonLibrary),
+    ]
+}
+
+/// the names of every [ClippingSource] returned by [sources]
+pub fn source_names() -> Vec<&'static str> {
+    sources().iter().map(|s| s.name()).collect()
+}
+
+/// find the [ClippingSource] with the given name
+pub fn source_by_name(name: &str) -> Option<Box<dyn ClippingSource>> {
+    sources()
+        .into_iter()
+        .find(|s| s.name().eq_ignore_ascii_case(name))
+}
+
+/// find the first [ClippingSource] that accepts the given file
+pub fn source_for_path(path: &Path) -> Option<Box<dyn ClippingSource>> {
+    sources().into_iter().find(|s| s.accepts(path))
+}
+
+/// case insensitive check of a file's extension
+pub(crate) fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| extensions.iter().any(|x| x.eq_ignore_ascii_case(e)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::source::{source_by_name, source_for_path};
+
+    #[test]
+    fn selection() {
+        assert_eq!(
+            "kindle",
+            source_for_path(Path::new("My Clippings.txt"))
+                .unwrap()
+                .name()
+        );
+        assert_eq!(
+            "kobo",
+            source_for_path(Path::new(".kobo/KoboReader.sqlite"))
+                .unwrap()
+                .name()
+        );
+        assert_eq!(
+            "readwise",
+            source_for_path(Path::new("readwise-data.csv"))
+                .unwrap()
+                .name()
+        );
+        assert!(source_for_path(Path::new("notes.docx")).is_none());
+
+        assert_eq!("kobo", source_by_name("Kobo").unwrap().name());
+        assert!(source_by_name("nook").is_none());
+    }
+}
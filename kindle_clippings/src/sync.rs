@@ -0,0 +1,325 @@
+//! Incremental sync, so re-running ktr after reading more doesn't undo the work put into notes it
+//! wrote before.
+//!
+//! Templates mark the parts of a note that ktr manages with lines containing `ktr:start NAME` and
+//! `ktr:end NAME`, in whatever comment syntax suits the format. Within a managed block, each
+//! highlight's entry starts at the first line containing the highlight's id and runs up to the next
+//! line with another highlight's id, or the end of the block.
+//!
+//! When a note already exists, the entries for highlights it doesn't have yet are added to the end
+//! of each of its managed blocks. Everything else, inside the blocks or out, is left as it was.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use regex::Regex;
+
+use crate::model::Book;
+use crate::output::{create_parent_dir, highlight_id, write_atomically, Format, RenderError};
+use crate::renderer::Renderer;
+
+/// What [sync_output] did to a note.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyncOutcome {
+    /// the note didn't exist, so it was written in full with this many highlights
+    Created(usize),
+    /// this many highlights were added to the existing note
+    Updated(usize),
+    /// the note already had every highlight
+    Unchanged,
+    /// the note has no managed blocks so it was left alone
+    Unmanaged,
+}
+
+/// The note [sync_output] looked at and what it did to it.
+#[derive(Debug, Clone)]
+pub struct SyncSummary {
+    pub file_path: PathBuf,
+    pub outcome: SyncOutcome,
+}
+
+impl std::fmt::Display for SyncSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let file = self.file_path.display();
+        match self.outcome {
+            SyncOutcome::Created(n) => write!(f, "Created {} with {} highlight(s)", file, n),
+            SyncOutcome::Updated(n) => write!(f, "Added {} highlight(s) to {}", n, file),
+            SyncOutcome::Unchanged => write!(f, "{} is up to date", file),
+            SyncOutcome::Unmanaged => {
+                write!(f, "Left {} alone, it has no ktr:start/ktr:end blocks", file)
+            }
+        }
+    }
+}
+
+/// A `ktr:start`/`ktr:end` pair, with the byte range of the lines between them.
+struct Block {
+    name: String,
+    content: Range<usize>,
+}
+
+/// Renders the [Book] as [render_output](crate::output::render_output) would, but if the note is
+/// already in the output [Path] only the highlights it doesn't have yet are added to it.
+pub fn sync_output(
+    book: &Book,
+    template: &Option<PathBuf>,
+    format: Format,
+    file_name: &str,
+    output_dir: &Path,
+) -> Result<SyncSummary, RenderError> {
+    Renderer::new(template, format, file_name)?.sync(book, output_dir)
+}
+
+/// sync the already `rendered` [Book] with the note at `file_path`
+pub(crate) fn sync_book(
+    book: &Book,
+    file_path: PathBuf,
+    rendered: &str,
+    backup: bool,
+) -> Result<SyncSummary, RenderError> {
+    let outcome = if file_path.exists() {
+        let existing = fs::read_to_string(&file_path)
+            .map_err(|e| RenderError::ReadExistingFailed(e.to_string()))?;
+
+        match merge_book(book, &existing, rendered) {
+            None => SyncOutcome::Unmanaged,
+            Some((_, 0)) => SyncOutcome::Unchanged,
+            Some((merged, added)) => {
+                write(&file_path, &merged, backup)?;
+                SyncOutcome::Updated(added)
+            }
+        }
+    } else {
+        write(&file_path, rendered, false)?;
+        SyncOutcome::Created(book.highlights().len())
+    };
+
+    Ok(SyncSummary { file_path, outcome })
+}
+
+fn write(file_path: &Path, content: &str, backup: bool) -> Result<(), RenderError> {
+    create_parent_dir(file_path)?;
+    write_atomically(file_path, backup, |writer| {
+        writer
+            .write_all(content.as_bytes())
+            .map_err(|e| RenderError::WriteFailed(e.to_string()))
+    })
+}
+
+/// [merge] the entries for the book's highlights from `rendered` into `existing`
+pub(crate) fn merge_book(book: &Book, existing: &str, rendered: &str) -> Option<(String, usize)> {
+    let ids: Vec<String> = book
+        .highlights()
+        .keys()
+        .map(|l| highlight_id(book, l).to_string())
+        .collect();
+
+    merge(existing, rendered, &ids)
+}
+
+/// add the entries from `rendered` for the highlights `existing` doesn't have to its blocks,
+/// returning the merged note and how many highlights were added, or [None] if `existing` has no
+/// managed blocks
+fn merge(existing: &str, rendered: &str, ids: &[String]) -> Option<(String, usize)> {
+    let existing_blocks = blocks(existing);
+    if existing_blocks.is_empty() {
+        return None;
+    }
+
+    let rendered_blocks = blocks(rendered);
+    let mut added: HashSet<&str> = HashSet::new();
+    let mut merged = String::with_capacity(existing.len());
+    let mut copied = 0;
+
+    for block in existing_blocks.iter() {
+        let Some(new) = rendered_blocks.iter().find(|b| b.name == block.name) else {
+            continue;
+        };
+
+        let current = &existing[block.content.clone()];
+        merged.push_str(&existing[copied..block.content.end]);
+        for (id, entry) in entries(&rendered[new.content.clone()], ids) {
+            if !current.contains(id) {
+                merged.push_str(&entry);
+                added.insert(id);
+            }
+        }
+        copied = block.content.end;
+    }
+    merged.push_str(&existing[copied..]);
+
+    Some((merged, added.len()))
+}
+
+/// the managed blocks in the note, blocks that are never closed are ignored
+fn blocks(note: &str) -> Vec<Block> {
+    let marker = Regex::new(r"ktr:(start|end)\s+([\w-]+)").unwrap();
+
+    let mut blocks: Vec<Block> = Vec::new();
+    let mut open: Option<(String, usize)> = None;
+    let mut offset = 0;
+
+    for line in note.split_inclusive('\n') {
+        if let Some(caps) = marker.captures(line) {
+            let name = caps[2].to_string();
+            match &caps[1] {
+                "start" => open = Some((name, offset + line.len())),
+                _ => {
+                    if let Some((open_name, start)) = open.take() {
+                        if open_name == name {
+                            blocks.push(Block {
+                                name,
+                                content: start..offset,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        offset += line.len();
+    }
+
+    blocks
+}
+
+/// the lines of each highlight's entry in a block, in the order they appear
+fn entries<'a>(block: &str, ids: &'a [String]) -> Vec<(&'a str, String)> {
+    let mut entries: Vec<(&str, String)> = Vec::new();
+
+    for line in block.split_inclusive('\n') {
+        let id = ids.iter().find(|id| line.contains(id.as_str()));
+        match (id, entries.last_mut()) {
+            (Some(id), Some((current, entry))) if *current == id.as_str() => entry.push_str(line),
+            (Some(id), _) => entries.push((id, line.to_string())),
+            (None, Some((_, entry))) => entry.push_str(line),
+            // anything before the first entry is the block's header
+            (None, None) => (),
+        }
+    }
+
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use crate::filename::DEFAULT_FILE_NAME;
+    use crate::model::{Book, Highlight, HighlightLocation, Note};
+    use crate::output::{highlight_id, Format};
+    use crate::sync::{sync_output, SyncOutcome};
+
+    fn book(highlights: u64) -> Book {
+        let mut book = Book::new("A Book".to_string(), "Doe, Jane".to_string());
+        for i in 1..=highlights {
+            book.add_highlight(Highlight::new(
+                i,
+                HighlightLocation::new(i * 10, i * 10 + 2),
+                format!("Quote {}", i),
+            ));
+        }
+        book.add_note(Note::new(1, 11, "A note".to_string()));
+        book
+    }
+
+    #[test]
+    fn keeps_edits() {
+        let dir = env::temp_dir().join(format!("ktr-sync-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("Doe, Jane. A Book.md");
+
+        let first =
+            sync_output(&book(1), &None, Format::Markdown, DEFAULT_FILE_NAME, &dir).unwrap();
+        assert_eq!(SyncOutcome::Created(1), first.outcome);
+
+        let note = fs::read_to_string(&file).unwrap();
+        let edited = note.replace("|       |", "| Resilience |") + "\nMy own thoughts\n";
+        fs::write(&file, &edited).unwrap();
+
+        let second =
+            sync_output(&book(2), &None, Format::Markdown, DEFAULT_FILE_NAME, &dir).unwrap();
+        let third =
+            sync_output(&book(2), &None, Format::Markdown, DEFAULT_FILE_NAME, &dir).unwrap();
+        let synced = fs::read_to_string(&file).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(SyncOutcome::Updated(1), second.outcome);
+        assert_eq!(SyncOutcome::Unchanged, third.outcome);
+
+        let id = highlight_id(&book(2), &HighlightLocation::new(20, 22));
+        assert!(synced.contains(&format!(
+            "| 1 | A note [^{}]| Resilience |\n| 2 |  [^{}]|       |\n<!-- ktr:end highlights -->",
+            highlight_id(&book(1), &HighlightLocation::new(10, 12)),
+            id
+        )));
+        assert!(synced.contains(&format!("[^{}]: Quote 2\n<!-- ktr:end quotes -->", id)));
+        assert!(synced.ends_with("\nMy own thoughts\n"));
+    }
+
+    #[test]
+    fn multi_line_entries() {
+        let dir = env::temp_dir().join(format!("ktr-sync-org-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("Doe, Jane. A Book.org");
+
+        sync_output(&book(1), &None, Format::Org, DEFAULT_FILE_NAME, &dir).unwrap();
+        let edited = fs::read_to_string(&file)
+            .unwrap()
+            .replace("A note\n", "A note, expanded on\n");
+        fs::write(&file, &edited).unwrap();
+        sync_output(&book(2), &None, Format::Org, DEFAULT_FILE_NAME, &dir).unwrap();
+
+        let synced = fs::read_to_string(&file).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(synced.contains("A note, expanded on\n"));
+        assert!(synced.contains("#+begin_quote\nQuote 2\n#+end_quote\n\n# ktr:end highlights"));
+        assert_eq!(1, synced.matches("* Location 20-22\n").count());
+    }
+
+    #[test]
+    fn logseq() {
+        let dir = env::temp_dir().join(format!("ktr-sync-logseq-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("Doe, Jane. A Book.md");
+
+        sync_output(&book(1), &None, Format::Logseq, DEFAULT_FILE_NAME, &dir).unwrap();
+        let edited = fs::read_to_string(&file)
+            .unwrap()
+            .replace("\t- A note\n", "\t- A note\n\t- A thought of my own\n");
+        fs::write(&file, &edited).unwrap();
+
+        let summary =
+            sync_output(&book(2), &None, Format::Logseq, DEFAULT_FILE_NAME, &dir).unwrap();
+        let synced = fs::read_to_string(&file).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        let id = highlight_id(&book(2), &HighlightLocation::new(20, 22));
+        assert_eq!(SyncOutcome::Updated(1), summary.outcome);
+        assert!(synced.contains("\t- A thought of my own\n#+BEGIN_COMMENT\n"));
+        assert!(synced.ends_with(&format!(
+            "ktr:highlight {}\n#+END_COMMENT\n- Quote 2\n  id:: {}\n  page:: 2\n  location:: 20-22\n#+BEGIN_COMMENT\nktr:end highlights\n#+END_COMMENT\n",
+            id, id
+        )));
+    }
+
+    #[test]
+    fn unmanaged() {
+        let dir = env::temp_dir().join(format!("ktr-sync-unmanaged-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("Doe, Jane. A Book.md");
+        fs::write(&file, "Written before sync existed\n").unwrap();
+
+        let summary =
+            sync_output(&book(2), &None, Format::Markdown, DEFAULT_FILE_NAME, &dir).unwrap();
+        let note = fs::read_to_string(&file).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(SyncOutcome::Unmanaged, summary.outcome);
+        assert_eq!("Written before sync existed\n", note);
+    }
+}
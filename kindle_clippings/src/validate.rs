@@ -0,0 +1,259 @@
+//! Checking a custom template before using it, by rendering it against a book and reporting what
+//! went wrong.
+//!
+//! Tera stops rendering at the first variable it can't find, so each undefined variable found is
+//! given an empty placeholder and the template rendered again, to report as many as possible in
+//! one go.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDate;
+use regex::Regex;
+use tera::Tera;
+
+use crate::model::{Book, Highlight, HighlightLocation, Note};
+use crate::output::{book_context, load_templates, tera_error, Format, RenderError};
+
+/// most undefined variables to look for before giving up
+const MAX_UNDEFINED: usize = 50;
+
+/// A variable a template uses that isn't in the context it was rendered with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Undefined {
+    pub name: String,
+    /// the template it was found in
+    pub template: String,
+    /// the first line of the template that uses it, if it could be found
+    pub line: Option<usize>,
+}
+
+impl std::fmt::Display for Undefined {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(l) => write!(
+                f,
+                "`{}` is undefined, used in '{}' on line {}",
+                self.name, self.template, l
+            ),
+            None => write!(
+                f,
+                "`{}` is undefined, used in '{}'",
+                self.name, self.template
+            ),
+        }
+    }
+}
+
+/// What [validate_template] found.
+#[derive(Debug, Default)]
+pub struct Report {
+    /// syntax errors and errors rendering the template, other than undefined variables
+    pub errors: Vec<String>,
+    pub undefined: Vec<Undefined>,
+    /// the note the template renders, if it could be rendered with placeholders for any undefined
+    /// variables
+    pub rendered: Option<String>,
+}
+
+impl Report {
+    /// whether the template rendered without any problems
+    pub fn is_ok(&self) -> bool {
+        self.errors.is_empty() && self.undefined.is_empty()
+    }
+}
+
+/// compile the template, a file or directory, and render it for the [Format] with the [Book]
+pub fn validate_template(template: &Path, format: Format, book: &Book) -> Report {
+    let mut report = Report::default();
+
+    let (tera, name) = match load_templates(&Some(template.to_path_buf()), format.template_name()) {
+        Ok(t) => t,
+        Err(RenderError::ParsingFailed(e)) | Err(RenderError::ReadExistingFailed(e)) => {
+            report.errors.push(e);
+            return report;
+        }
+        Err(e) => {
+            report.errors.push(e.to_string());
+            return report;
+        }
+    };
+
+    let not_found =
+        Regex::new(r"Variable `(.+?)` not found in context while rendering '(.+?)'").unwrap();
+    let mut ctx = book_context(book);
+
+    while report.undefined.len() < MAX_UNDEFINED {
+        let e = match tera.render(&name, &ctx) {
+            Ok(rendered) => {
+                report.rendered = Some(rendered);
+                break;
+            }
+            Err(e) => tera_error(&e),
+        };
+
+        let Some(caps) = not_found.captures(&e) else {
+            report.errors.push(e);
+            break;
+        };
+
+        let variable = caps[1].to_string();
+        report.undefined.push(Undefined {
+            line: first_use(&tera, template, &caps[2], &variable),
+            name: variable.clone(),
+            template: caps[2].to_string(),
+        });
+
+        // only top level variables can be filled in, `hl.missing` would need `hl` replacing
+        if ctx.contains_key(&variable) || !is_identifier(&variable) {
+            break;
+        }
+        ctx.insert(variable, "");
+    }
+
+    report
+}
+
+fn is_identifier(s: &str) -> bool {
+    s.chars().all(|c| c.is_alphanumeric() || c == '_')
+}
+
+/// the first line of the named template that uses `variable` in a tag
+fn first_use(tera: &Tera, template: &Path, name: &str, variable: &str) -> Option<usize> {
+    let path = match tera.get_template(name).ok()?.path.as_ref() {
+        Some(p) => PathBuf::from(p),
+        None => template.to_path_buf(),
+    };
+    let source = fs::read_to_string(path).ok()?;
+
+    let tag = Regex::new(r"\{\{.*?\}\}|\{%.*?%\}").unwrap();
+    let word = Regex::new(&format!(r"\b{}\b", regex::escape(variable))).unwrap();
+
+    source
+        .lines()
+        .position(|line| tag.find_iter(line).any(|t| word.is_match(t.as_str())))
+        .map(|i| i + 1)
+}
+
+/// a small book with every kind of highlight, for checking templates without a library to hand
+pub fn sample_book() -> Book {
+    let mut book = Book::new(
+        "The Sample Book: A Guide to Reading".to_string(),
+        "Reader, Avid".to_string(),
+    );
+    let date = |day, hour| {
+        NaiveDate::from_ymd_opt(2024, 5, day)
+            .unwrap()
+            .and_hms_opt(hour, 30, 0)
+            .unwrap()
+    };
+
+    let mut first = Highlight::new(
+        12,
+        HighlightLocation::new(180, 182),
+        "Reading is to the mind what exercise is to the body.".to_string(),
+    );
+    first.add_date(date(1, 8));
+    first.add_chapter("Chapter 1: Why We Read".to_string());
+    first.add_tag("reading".to_string());
+    book.add_highlight(first);
+
+    let mut second = Highlight::new(
+        57,
+        HighlightLocation::new(840, 840),
+        "A quote that runs over\nmore than one line.".to_string(),
+    );
+    second.add_date(date(3, 21));
+    second.add_chapter("Chapter 4: Taking Notes".to_string());
+    book.add_highlight(second);
+
+    book.add_highlight(Highlight::new(
+        101,
+        HighlightLocation::new(1512, 1514),
+        "A highlight without a date, chapter, tags or note.".to_string(),
+    ));
+
+    book.add_note(Note::new(
+        12,
+        181,
+        "Start every morning with a chapter".to_string(),
+    ));
+    book
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+
+    use crate::output::Format;
+    use crate::validate::{sample_book, validate_template, Undefined};
+
+    #[test]
+    fn default_templates() {
+        let dir = env::temp_dir().join(format!("ktr-validate-default-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        for format in Format::ALL {
+            // an empty directory falls back to the built-in templates
+            let report = validate_template(&dir, format, &sample_book());
+            assert!(report.is_ok(), "{}: {:?}", format, report);
+            assert!(report.rendered.unwrap().contains("Reading is to the mind"));
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn problems() {
+        let dir = env::temp_dir().join(format!("ktr-validate-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let undefined = dir.join("undefined.md");
+        fs::write(
+            &undefined,
+            "# {{ title }}\n{{ subtitle }}\n\n{% for hl in highlights %}{{ isbn }}{% endfor %}\n",
+        )
+        .unwrap();
+        let syntax = dir.join("syntax.md");
+        fs::write(&syntax, "# {{ title }}\n\nBy {{ author | }}\n").unwrap();
+        let nested = dir.join("nested.md");
+        fs::write(&nested, "{{ book.isbn }}{{ other }}").unwrap();
+
+        let undefined = validate_template(&undefined, Format::Markdown, &sample_book());
+        let syntax = validate_template(&syntax, Format::Markdown, &sample_book());
+        let nested = validate_template(&nested, Format::Markdown, &sample_book());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert!(undefined.errors.is_empty());
+        assert_eq!(
+            vec![
+                Undefined {
+                    name: "subtitle".to_string(),
+                    template: "user".to_string(),
+                    line: Some(2)
+                },
+                Undefined {
+                    name: "isbn".to_string(),
+                    template: "user".to_string(),
+                    line: Some(4)
+                }
+            ],
+            undefined.undefined
+        );
+        assert_eq!(
+            "# The Sample Book: A Guide to Reading\n\n\n\n",
+            undefined.rendered.unwrap()
+        );
+        assert_eq!(
+            "`subtitle` is undefined, used in 'user' on line 2",
+            undefined.undefined[0].to_string()
+        );
+
+        assert!(syntax.rendered.is_none());
+        assert!(syntax.errors[0].contains("--> 3:"), "{}", syntax.errors[0]);
+
+        // `book.isbn` can't be filled in, so `other` is never reached
+        assert_eq!(1, nested.undefined.len());
+        assert_eq!("book.isbn", nested.undefined[0].name);
+        assert!(nested.rendered.is_none());
+    }
+}